@@ -1,44 +1,357 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
 
 use bluest::AdvertisingDevice;
 use eframe::{egui::{self, Ui, RichText}, epaint::Color32};
-use egui_plot::{BarChart, Bar, Legend, Plot};
+use egui_plot::{
+    AxisHints, BarChart, Bar, HPlacement, Legend, Line, LineStyle, Plot, PlotPoints,
+};
 use futures_lite::StreamExt;
 use tokio::{
     runtime::Runtime,
     sync::{
-        mpsc::{self, Receiver},
-        oneshot,
+        mpsc::{self, Receiver, Sender},
+        broadcast, oneshot, watch,
+    },
+};
+
+use crate::{
+    dashboard::{self, Dashboard, HistoryRow, Metrics},
+    erg::{CadenceController, ErgController, HeartRateController, SpiralGuard},
+    export::{csv, fit, tcx, SessionRecord},
+    ftms::{
+        self, BikeSample, InclinationRange, MachineFeatures, MachineStatus, PowerRange,
+        ResistanceRange, SpinDownStatus, SpinDownTarget, TrainingStatus,
+    },
+    metrics::{
+        self, DistanceAccumulator, EnergyAccumulator, MeanMaxPower, NpCalculator, PowerFilter,
+        PEAK_DURATIONS,
     },
+    session::Session,
+    settings::{CadencePoint, FavoriteDevice, PowerBias, Settings, Units},
+    spike::SpikeFilter,
+    timer::{IntervalTimer, TimerEvent},
+    trainer::{
+        self, ControlCommand, DeviceInfo, DeviceKind, ReconnectPolicy, ScanOptions,
+        TrainerUpdate, BT,
+    },
+    upload::{garmin, strava, UploadEvent},
+    workout::Workout,
+    zones::{PowerZones, Zone},
 };
+#[cfg(feature = "serde")]
+use crate::history::{self, HistoryScreen};
+
+const SPEED_AXIS_SCALE: f64 = 10.0;
+const RAW_PACKET_CAPACITY: usize = 20;
+const UPCOMING_STEPS: usize = 5;
+const RESISTANCE_DEBOUNCE: Duration = Duration::from_millis(300);
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+const STALE_AFTER: Duration = Duration::from_secs(3);
+const LOST_AFTER: Duration = Duration::from_secs(10);
+const LOW_BATTERY_PCT: u8 = 15;
+// The stopwatch stops when the trainer goes quiet for this long.
+const STOPWATCH_IDLE: Duration = Duration::from_secs(5);
+// Keys nudging the ERG target and the step each applies, repeating while held.
+const POWER_KEYS: [(egui::Key, i32); 4] = [
+    (egui::Key::ArrowUp, 5),
+    (egui::Key::ArrowDown, -5),
+    (egui::Key::PageUp, 25),
+    (egui::Key::PageDown, -25),
+];
+const KIOSK_FONT_SIZE: f32 = 24.0;
+const KEY_REPEAT_DELAY: Duration = Duration::from_millis(400);
+const KEY_REPEAT_INTERVAL: Duration = Duration::from_millis(150);
+const ROLLING_WINDOWS: [Duration; 3] = [
+    Duration::from_secs(3),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+const DASHBOARD_UPDATES_CAPACITY: usize = 256;
+const MAX_BIAS_WATTS: i16 = 30;
+const MAX_BIAS_PCT: f32 = 10.0;
+// One-click ERG targets, as a percentage of FTP.
+const FTP_PRESETS: [(&str, u16); 3] = [("Warm up", 50), ("Endurance", 65), ("Cool down", 40)];
+
+struct DeviceHandle {
+    name: String,
+    kind: DeviceKind,
+    info: DeviceInfo,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+/// An update tagged with the device it came from, `None` for the control queue.
+struct TaggedUpdate {
+    device: Option<String>,
+    update: TrainerUpdate,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ControlTab {
+    Power,
+    Resistance,
+    Simulation,
+}
+
+impl ControlTab {
+    const ALL: [ControlTab; 3] = [
+        ControlTab::Power,
+        ControlTab::Resistance,
+        ControlTab::Simulation,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            ControlTab::Power => "Power",
+            ControlTab::Resistance => "Resistance",
+            ControlTab::Simulation => "Simulation",
+        }
+    }
+}
+
+enum SpinDown {
+    Starting,
+    SpeedUp(SpinDownTarget),
+    StopPedaling { since: Instant },
+    Done(Option<Duration>),
+    Failed(String),
+}
+
+struct IntervalSetup {
+    work_secs: u64,
+    rest_secs: u64,
+    repeats: u32,
+    set_power: bool,
+    work_watts: u16,
+    rest_watts: u16,
+}
+
+impl Default for IntervalSetup {
+    fn default() -> Self {
+        Self {
+            work_secs: 60,
+            rest_secs: 60,
+            repeats: 5,
+            set_power: false,
+            work_watts: 250,
+            rest_watts: 120,
+        }
+    }
+}
+
+struct RunningIntervals {
+    rx: mpsc::Receiver<TimerEvent>,
+    // Dropping the sender stops the timer.
+    _stop: oneshot::Sender<()>,
+    current: Option<TimerEvent>,
+}
+
+/// Scan for the trainer used last time, started on launch.
+struct AutoConnect {
+    name: String,
+    rx: mpsc::Receiver<AdvertisingDevice>,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+impl AutoConnect {
+    fn start(rt: &Runtime, bt: &BT, device_id: String, name: String) -> Self {
+        let (tx, rx) = mpsc::channel(1);
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let mut bt = bt.clone();
+
+        rt.spawn(async move {
+            let options = ScanOptions {
+                device_id: Some(device_id),
+                ..Default::default()
+            };
+            let mut devices = match bt.discover_devices(options).await {
+                Ok(devices) => devices,
+                Err(e) => {
+                    tracing::error!("Failed to scan for last device: {:?}", e);
+                    return;
+                }
+            };
+
+            tokio::select! {
+                Some(device) = devices.next() => {
+                    let _ = tx.send(device).await;
+                }
+                _ = &mut stop_rx => {}
+            }
+        });
+
+        Self {
+            name,
+            rx,
+            stop: Some(stop_tx),
+        }
+    }
+}
 
-use crate::trainer::{TrainerUpdate, BT};
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    Connected,
+    Reconnecting,
+    Lost,
+}
 
-pub(crate) fn run() -> Result<(), eframe::Error> {
+pub(crate) fn run(kiosk: bool, web_dashboard: bool) -> Result<(), eframe::Error> {
+    let settings = Settings::load();
+    if kiosk {
+        tracing::warn!("Kiosk mode can't keep the screen awake, disable sleep in the OS settings");
+    }
     let options = eframe::NativeOptions {
-        initial_window_size: Some(egui::vec2(320.0, 240.0)),
+        initial_window_size: Some(egui::vec2(settings.window_width, settings.window_height)),
+        fullscreen: kiosk,
+        decorated: !kiosk,
         ..Default::default()
     };
     eframe::run_native(
         "Simple Trainer 0.1",
         options,
-        Box::new(|_cc| Box::<App>::default()),
+        Box::new(move |cc| {
+            let mut app = App::default();
+            // Applied before the first frame so the saved theme and scale show right away.
+            cc.egui_ctx.set_visuals(visuals(app.settings.dark_mode));
+            app.native_pixels_per_point =
+                cc.integration_info.native_pixels_per_point.unwrap_or(1.0);
+            app.apply_ui_scale(&cc.egui_ctx);
+            if kiosk {
+                app.kiosk = true;
+                enlarge_text(&cc.egui_ctx, KIOSK_FONT_SIZE);
+            }
+            if web_dashboard {
+                app.start_dashboard();
+            }
+            Box::new(app)
+        }),
     )
 }
 
 struct App {
     rt: Runtime,
     bt: BT,
+    settings: Settings,
+    // The OS scale factor, the UI scale setting zooms on top of it.
+    native_pixels_per_point: f32,
+    // Fullscreen tablet layout, set from the command line.
+    kiosk: bool,
+    dashboard_tx: Option<watch::Sender<Dashboard>>,
+    dashboard_updates: Option<broadcast::Sender<String>>,
+    show_settings: bool,
+    // Some while the history window is open, scanned when it opens.
+    #[cfg(feature = "serde")]
+    history: Option<HistoryScreen>,
+    focus_ftp: bool,
+    confirm_unfavorite: Option<FavoriteDevice>,
+    // Some(clear_data) while the reset confirmation dialog is open.
+    confirm_reset: Option<bool>,
     discover_rx: Option<mpsc::Receiver<AdvertisingDevice>>,
     discover_stop: Option<oneshot::Sender<()>>,
+    auto_connect: Option<AutoConnect>,
     devices: HashMap<String, AdvertisingDevice>,
+    scan_sensors: bool,
+    name_prefix: String,
     connecting: bool,
-    connected_device: Option<Receiver<TrainerUpdate>>,
-    connected_rx: Option<oneshot::Receiver<Receiver<TrainerUpdate>>>,
-    current_speed: u16,
-    current_power: u16,
-    historical_speeds: Vec<u16>,
-    historical_powers: Vec<u16>,
+    link_state: LinkState,
+    connected_devices: Vec<DeviceHandle>,
+    connections_tx: mpsc::UnboundedSender<Result<DeviceHandle, String>>,
+    connections_rx: mpsc::UnboundedReceiver<Result<DeviceHandle, String>>,
+    updates_tx: Sender<TaggedUpdate>,
+    updates_rx: Receiver<TaggedUpdate>,
+    // When each connected device last sent something.
+    last_seen: HashMap<String, Instant>,
+    battery_levels: HashMap<String, u8>,
+    commands_tx: Sender<ControlCommand>,
+    current: BikeSample,
+    current_distance: f64,
+    current_energy: Option<u16>,
+    energy_origin: Option<u16>,
+    energy_offset: u16,
+    target_power: u16,
+    // Set when the target was nudged, until the trainer echoes a new target back.
+    target_pending: bool,
+    // Power key being held and when it next repeats.
+    held_power_key: Option<(egui::Key, Instant)>,
+    erg: Option<ErgController>,
+    spiral_guard: SpiralGuard,
+    hr_control: Option<HeartRateController>,
+    cadence_control: Option<CadenceController>,
+    control_tab: ControlTab,
+    grade: f32,
+    inclination_range: InclinationRange,
+    // The grade asked for when it was outside what the trainer supports.
+    clamped_grade: Option<f32>,
+    // Grade last sent to the trainer, after difficulty scaling and clamping.
+    applied_grade: Option<f32>,
+    // Virtual gear relative to the middle one, each step shifts the grade by gear_step_pct.
+    gear: i32,
+    wind_speed: f32,
+    crr: f32,
+    cw: f32,
+    // None until the trainer reports its range, resistance control is hidden without one.
+    resistance_range: Option<ResistanceRange>,
+    power_range: PowerRange,
+    // None until the trainer reports its features, everything is offered meanwhile.
+    features: Option<MachineFeatures>,
+    target_resistance: f32,
+    resistance_changed: Option<Instant>,
+    control_error: Option<String>,
+    machine_status: Option<MachineStatus>,
+    training_status: Option<TrainingStatus>,
+    // Target power the trainer last confirmed through a status notification.
+    accepted_power: Option<i16>,
+    has_control: bool,
+    spin_down: Option<SpinDown>,
+    interval_setup: IntervalSetup,
+    intervals: Option<RunningIntervals>,
+    workout: Option<Workout>,
+    workout_started: Option<Instant>,
+    workout_step: Option<usize>,
+    distance_origin: Option<u32>,
+    distance_offset: f64,
+    integrated_distance: DistanceAccumulator,
+    last_update: Option<Instant>,
+    sample_arrivals: VecDeque<Instant>,
+    speed_seen: Option<Instant>,
+    cadence_seen: Option<Instant>,
+    power_seen: Option<Instant>,
+    spike_filter: SpikeFilter,
+    power_filter: PowerFilter,
+    connected_at: Option<Instant>,
+    paused_at: Option<Instant>,
+    paused_total: Duration,
+    session_start: Option<Instant>,
+    last_data: Option<Instant>,
+    idle_total: Duration,
+    historical_speeds: VecDeque<(f64, f32)>,
+    historical_powers: VecDeque<(f64, u16)>,
+    rolling_powers: [VecDeque<(Instant, u16)>; 3],
+    np: NpCalculator,
+    peaks: MeanMaxPower,
+    work: EnergyAccumulator,
+    final_tss: Option<f32>,
+    // The last FIT export, offered for upload.
+    fit_export: Option<PathBuf>,
+    upload_rx: Option<Receiver<UploadEvent>>,
+    upload_status: Option<String>,
+    upload_url: Option<String>,
+    // Typed into the settings and moved to the keychain, never saved with the settings.
+    strava_client_secret: String,
+    strava_client_secret_status: Option<String>,
+    garmin_password: String,
+    garmin_password_status: Option<String>,
+    raw_packets: VecDeque<Vec<u8>>,
+    historical_cadences: VecDeque<(f64, f32)>,
+    historical_heart_rates: VecDeque<(f64, u8)>,
+    last_rr_interval: Option<u16>,
+    records: Vec<SessionRecord>,
+    session: Session,
+    show_bar_chart: bool,
 }
 
 impl Default for App {
@@ -46,193 +359,3501 @@ impl Default for App {
         let rt = Runtime::new().unwrap();
 
         let bt = rt.block_on(async { BT::init().await.unwrap() });
+        let (updates_tx, updates_rx) = mpsc::channel(1024);
+        let (connections_tx, connections_rx) = mpsc::unbounded_channel();
+
+        let settings = Settings::load();
+        let power_filter = PowerFilter::new(settings.power_smoothing);
+        let commands_tx = {
+            let _guard = rt.enter();
+            let interval = Duration::from_millis(settings.simulation_interval_ms);
+            let (control_tx, mut control_rx) = mpsc::channel(64);
+            let updates_tx = updates_tx.clone();
+            tokio::spawn(async move {
+                while let Some(update) = control_rx.recv().await {
+                    let tagged = TaggedUpdate {
+                        device: None,
+                        update,
+                    };
+                    if let Err(_) = updates_tx.send(tagged).await {
+                        tracing::error!("Channel closed");
+                        break;
+                    }
+                }
+            });
+            bt.spawn_control_queue(control_tx, interval)
+        };
+        let auto_connect = settings.last_device.clone().map(|id| {
+            let name = settings.last_device_name.clone().unwrap_or_else(|| id.clone());
+            tracing::info!("Looking for last device {}", name);
+            AutoConnect::start(&rt, &bt, id, name)
+        });
 
         Self {
             rt,
             bt,
+            settings,
+            native_pixels_per_point: 1.0,
+            kiosk: false,
+            dashboard_tx: None,
+            dashboard_updates: None,
+            show_settings: false,
+            #[cfg(feature = "serde")]
+            history: None,
+            focus_ftp: false,
+            confirm_unfavorite: None,
+            confirm_reset: None,
             discover_rx: None,
             discover_stop: None,
+            auto_connect,
             devices: HashMap::new(),
+            scan_sensors: false,
+            name_prefix: String::new(),
             connecting: false,
-            connected_device: None,
-            connected_rx: None,
-            current_speed: 0,
-            current_power: 0,
-            historical_speeds: vec![],
-            historical_powers: vec![],
+            link_state: LinkState::Connected,
+            connected_devices: Vec::new(),
+            connections_tx,
+            connections_rx,
+            updates_tx,
+            updates_rx,
+            last_seen: HashMap::new(),
+            battery_levels: HashMap::new(),
+            commands_tx,
+            current: BikeSample::default(),
+            current_distance: 0.0,
+            current_energy: None,
+            energy_origin: None,
+            energy_offset: 0,
+            target_power: 150,
+            target_pending: false,
+            held_power_key: None,
+            erg: None,
+            hr_control: None,
+            cadence_control: None,
+            spiral_guard: SpiralGuard::new(
+                settings.spiral_cadence_floor,
+                settings.spiral_reduction_pct / 100.0,
+            ),
+            control_tab: ControlTab::Power,
+            grade: 0.0,
+            inclination_range: InclinationRange::default(),
+            clamped_grade: None,
+            applied_grade: None,
+            gear: 0,
+            wind_speed: 0.0,
+            crr: trainer::DEFAULT_CRR,
+            cw: trainer::DEFAULT_CW,
+            resistance_range: None,
+            power_range: PowerRange::default(),
+            features: None,
+            target_resistance: 0.0,
+            resistance_changed: None,
+            control_error: None,
+            machine_status: None,
+            training_status: None,
+            accepted_power: None,
+            has_control: true,
+            spin_down: None,
+            interval_setup: IntervalSetup::default(),
+            intervals: None,
+            workout: None,
+            workout_started: None,
+            workout_step: None,
+            distance_origin: None,
+            distance_offset: 0.0,
+            integrated_distance: DistanceAccumulator::default(),
+            last_update: None,
+            sample_arrivals: VecDeque::new(),
+            speed_seen: None,
+            cadence_seen: None,
+            power_seen: None,
+            spike_filter: SpikeFilter::default(),
+            power_filter,
+            connected_at: None,
+            paused_at: None,
+            paused_total: Duration::ZERO,
+            session_start: None,
+            last_data: None,
+            idle_total: Duration::ZERO,
+            historical_speeds: VecDeque::new(),
+            historical_powers: VecDeque::new(),
+            rolling_powers: Default::default(),
+            np: NpCalculator::default(),
+            peaks: MeanMaxPower::default(),
+            work: EnergyAccumulator::default(),
+            final_tss: None,
+            fit_export: None,
+            upload_rx: None,
+            upload_status: None,
+            upload_url: None,
+            strava_client_secret: String::new(),
+            strava_client_secret_status: None,
+            garmin_password: String::new(),
+            garmin_password_status: None,
+            raw_packets: VecDeque::new(),
+            historical_cadences: VecDeque::new(),
+            historical_heart_rates: VecDeque::new(),
+            last_rr_interval: None,
+            records: vec![],
+            session: Session::default(),
+            show_bar_chart: false,
         }
     }
 }
 
 impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ctx.set_pixels_per_point(5.0);
-            match &self.connected_device {
-                Some(_) => {
-                    self.render_connected_screen(ui);
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        // on_exit has no access to the frame, keep the size current for it.
+        let window = &frame.info().window_info;
+        if !window.minimized && window.size.x > 0.0 && window.size.y > 0.0 {
+            self.settings.window_width = window.size.x;
+            self.settings.window_height = window.size.y;
+        }
+
+        egui::TopBottomPanel::top("toolbar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("⚙").on_hover_text("Settings").clicked() {
+                    self.show_settings = !self.show_settings;
                 }
-                None => {
-                    self.render_setup_screen(ui, ctx);
+                #[cfg(feature = "serde")]
+                if ui.button("🗄").on_hover_text("History").clicked() {
+                    self.toggle_history();
                 }
-            };
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let (icon, hover) = if self.settings.dark_mode {
+                        ("☀", "Switch to light theme")
+                    } else {
+                        ("🌙", "Switch to dark theme")
+                    };
+                    if ui.button(icon).on_hover_text(hover).clicked() {
+                        self.settings.dark_mode = !self.settings.dark_mode;
+                        ctx.set_visuals(visuals(self.settings.dark_mode));
+                        self.save_settings();
+                    }
+                });
+            });
+        });
+
+        self.render_settings_window(ctx);
+        #[cfg(feature = "serde")]
+        self.render_history_window(ctx);
+        self.render_spin_down_window(ctx);
+        self.render_unfavorite_window(ctx);
+        self.render_reset_window(ctx);
+
+        if !self.connected_devices.is_empty() {
+            egui::SidePanel::left("devices").show(ctx, |ui| self.render_devices_panel(ui, ctx));
+        }
 
-            self.update_discovery()
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if self.auto_connect.is_some() {
+                self.render_auto_connect(ui);
+            } else if self.connected_devices.is_empty() {
+                self.render_setup_screen(ui, ctx);
+            } else {
+                self.render_connected_screen(ui);
+            }
+
+            self.update_auto_connect(ctx);
+            self.update_discovery();
+            self.update_dropped_files(ctx);
+            self.update_workout();
+            self.update_intervals();
+            self.update_resistance();
+            self.update_power_keys(ctx);
+            self.update_gear_keys(ctx);
+            self.update_bias_keys(ctx);
+            self.update_cadence_control(ctx);
+            self.update_upload(ctx);
         });
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Err(e) = self.settings.save() {
+            tracing::error!("Failed to save settings: {}", e);
+        }
+    }
 }
 
 impl App {
     fn render_connected_screen(&mut self, ui: &mut Ui) {
-        ui.heading("Simple Trainer 0.1");
+        // Kiosk screens have the room to show readouts and controls side by side.
+        if self.kiosk {
+            ui.columns(2, |columns| {
+                self.render_ride_data(&mut columns[0]);
+                self.render_ride_controls(&mut columns[1]);
+            });
+        } else {
+            self.render_ride_data(ui);
+            self.render_ride_controls(ui);
+        }
+    }
+
+    fn render_ride_data(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Simple Trainer 0.1");
+            let elapsed = self.stopwatch().as_secs();
+            ui.heading(format!(
+                "{:02}:{:02}:{:02}",
+                elapsed / 3600,
+                elapsed / 60 % 60,
+                elapsed % 60
+            ));
+        });
+
+        match self.link_state {
+            LinkState::Connected => {}
+            LinkState::Reconnecting => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Reconnecting…");
+                });
+            }
+            LinkState::Lost => {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Connection lost").color(Color32::RED));
+                    if ui.button("Retry").clicked() {
+                        self.retry_trainer(ui.ctx());
+                    }
+                });
+            }
+        }
+
+        ui.label(format!("{:.1} Hz", self.notification_rate()));
+        // Re-check staleness even when no notifications arrive.
+        ui.ctx().request_repaint_after(Duration::from_secs(1));
 
         ui.horizontal(|ui| {
             ui.label("Speed: ");
-            ui.label(RichText::new(format!("{} km/h", self.current_speed / 100)).color(Color32::GREEN));
+            let speed = match self.current.speed {
+                Some(speed) => self.settings.units.format_speed(speed as f64),
+                None => "-".to_string(),
+            };
+            ui.label(RichText::new(speed).color(self.freshness_color()));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Cadence: ");
+            let cadence = match self.current.cadence {
+                Some(cadence) => format!("{:.0} rpm", cadence),
+                None => "-".to_string(),
+            };
+            ui.label(RichText::new(cadence).color(Color32::GREEN));
         });
 
         ui.horizontal(|ui| {
             ui.label("Power: ");
-            ui.label(RichText::new(format!("{} watts", self.current_power)).color(Color32::GREEN));
+            let power = match self.current.power {
+                Some(power) => format!("{} watts", power),
+                None => "-".to_string(),
+            };
+            ui.label(RichText::new(power).color(self.freshness_color()));
+            if let Some(power) = self.current.power {
+                match metrics::watts_per_kg(power, self.settings.weight_kg) {
+                    Some(wkg) => {
+                        ui.label(RichText::new(format!("{:.2} W/kg", wkg)).color(Color32::GREEN));
+                    }
+                    None => {
+                        ui.label("- W/kg").on_hover_text("Enter your body weight in Settings");
+                    }
+                }
+            }
+            if self.current.power.is_some() {
+                ui.label(format!(
+                    "3s: {}  10s: {}  30s: {}",
+                    self.rolling_avg_power(ROLLING_WINDOWS[0]),
+                    self.rolling_avg_power(ROLLING_WINDOWS[1]),
+                    self.rolling_avg_power(ROLLING_WINDOWS[2]),
+                ));
+            }
         });
 
-        let bars = self.historical_powers.iter().enumerate().map(|(i, p)| {
-            Bar::new(i as f64, *p as f64)
-        }).collect();
+        if let Some(power) = self.current.power {
+            self.render_zone_bar(ui, power);
+        }
 
-        let chart = BarChart::new(bars);
+        if self.np.is_ready() {
+            let np = self.np.normalized_power();
+            let intensity = metrics::intensity_factor(np, self.settings.ftp);
+            ui.horizontal(|ui| {
+                let np_wkg = metrics::watts_per_kg(np, self.settings.weight_kg)
+                    .map_or(String::new(), |wkg| format!(" ({:.2} W/kg)", wkg));
+                ui.label(
+                    RichText::new(format!("NP: {} watts{}", np, np_wkg))
+                        .size(18.0)
+                        .color(Color32::GREEN),
+                );
+                ui.label(
+                    RichText::new(format!("IF: {:.2}", intensity))
+                        .size(18.0)
+                        .color(Color32::GREEN),
+                );
+                if self.final_tss.is_none() {
+                    let tss = metrics::tss(self.session_duration(), np, self.settings.ftp);
+                    ui.label(
+                        RichText::new(format!("TSS: {:.0}", tss))
+                            .size(18.0)
+                            .color(Color32::GREEN),
+                    );
+                }
+            });
+        }
 
-        Plot::new("Power")
-            .legend(Legend::default())
-            .clamp_grid(true)
-            .y_axis_width(3)
-            .show(ui, |plot_ui| plot_ui.bar_chart(chart))
-            .response;
-    }
+        if let Some(tss) = self.final_tss {
+            ui.label(
+                RichText::new(format!("Session TSS: {:.0}", tss))
+                    .size(24.0)
+                    .strong()
+                    .color(Color32::GREEN),
+            );
+        }
 
-    fn render_setup_screen(&mut self, ui: &mut Ui, ctx: &egui::Context) {
-        ui.heading("Simple Trainer 0.1");
+        if !self.records.is_empty() {
+            let samples = self.records.len() as f64;
+            let average_speed = self.records.iter().map(|r| r.speed as f64).sum::<f64>() / samples;
+            let average_power = self.records.iter().map(|r| r.power as f64).sum::<f64>() / samples;
 
-        match self.discover_rx {
-            Some(_) => {
-                if ui.button("Stop Discovery").clicked() {
-                    self.stop_discover();
+            ui.horizontal(|ui| {
+                ui.label("Avg speed: ");
+                let speed = self.settings.units.format_speed(average_speed);
+                ui.label(RichText::new(speed).color(Color32::GREEN));
+                if let Some(trainer_speed) = self.current.average_speed {
+                    let speed = self.settings.units.format_speed(trainer_speed as f64);
+                    ui.label(format!("(trainer: {})", speed));
                 }
-            }
-            None => {
-                if ui.button("Discover").clicked() {
-                    self.start_discover();
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Avg power: ");
+                ui.label(
+                    RichText::new(format!("{:.0} watts", average_power)).color(Color32::GREEN),
+                );
+                if let Some(trainer_power) = self.current.average_power {
+                    ui.label(format!("(trainer: {} watts)", trainer_power));
                 }
-            }
+            });
         }
 
-        let devices = self.devices.clone();
+        ui.horizontal(|ui| {
+            ui.label("Heart rate: ");
+            let heart_rate = match self.current.heart_rate {
+                Some(bpm) => format!("{} bpm", bpm),
+                None => "-".to_string(),
+            };
+            ui.label(RichText::new(heart_rate).color(Color32::GREEN));
+            if let Some(rr) = self.last_rr_interval.filter(|_| self.current.heart_rate.is_some()) {
+                ui.label(format!("(RR {} ms)", rr));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Distance: ");
+            let distance = self.settings.units.format_distance(self.current_distance);
+            ui.label(RichText::new(distance).color(Color32::GREEN));
+        });
 
-        if self.connecting {
+        ui.horizontal(|ui| {
+            ui.label("Energy: ");
+            let energy = match self.current_energy {
+                Some(kcal) => format!("{} kcal", kcal),
+                None => "-".to_string(),
+            };
+            ui.label(RichText::new(energy).color(Color32::GREEN));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Work: ");
+            let work = format!("{:.0} kJ (~{:.0} kcal)", self.work.kj(), self.work.kcal());
+            ui.label(RichText::new(work).color(Color32::GREEN));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Resistance: ");
+            let resistance = match self.current.resistance {
+                Some(resistance) => resistance.to_string(),
+                None => "-".to_string(),
+            };
+            ui.label(RichText::new(resistance).color(Color32::GREEN));
+        });
+
+        if let Some(remaining) = self.current.remaining_time {
             ui.horizontal(|ui| {
-                ui.spinner();
+                ui.label("Remaining: ");
+                ui.label(
+                    RichText::new(format!("{}:{:02}", remaining / 60, remaining % 60))
+                        .color(Color32::GREEN),
+                );
             });
-        } else {
-            devices.keys().for_each(|k| {
-                ui.horizontal(|ui| {
-                    if ui.link(k.clone()).clicked() {
-                        self.connect(k.clone(), ctx);
-                    }
-                });
+        }
+
+        ui.collapsing("Details", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Metabolic equivalent: ");
+                let met = match self.current.metabolic_equivalent {
+                    Some(met) => format!("{:.1}", met),
+                    None => "-".to_string(),
+                };
+                ui.label(met);
             });
+        });
+
+        ui.collapsing("Peak power", |ui| {
+            let peaks: Vec<String> = PEAK_DURATIONS
+                .iter()
+                .zip(self.peaks.bests())
+                .map(|(duration, peak)| {
+                    let peak = peak.map_or("-".to_string(), |watts| format!("{} W", watts));
+                    format!("{}: {}", metrics::peak_label(*duration), peak)
+                })
+                .collect();
+            ui.label(peaks.join(" | "));
+
+            // The 20 min best is what FTP estimates and W/kg comparisons are built on.
+            let twenty_minutes = self.peaks.bests()[PEAK_DURATIONS.len() - 1];
+            let wkg = twenty_minutes
+                .and_then(|watts| metrics::watts_per_kg(watts, self.settings.weight_kg));
+            if let Some(wkg) = wkg {
+                ui.label(format!("20 min: {:.2} W/kg", wkg));
+            }
+        });
+
+        if !self.raw_packets.is_empty() {
+            ui.collapsing("Debug", |ui| self.render_raw_packets(ui));
         }
     }
 
-    fn update_discovery(&mut self) {
-        if let Some(ref mut rx) = self.connected_device {
-            if let Ok(update) = rx.try_recv() {
-                match update {
-                    TrainerUpdate::Power { speed, power } => {
-                        self.current_speed = speed;
-                        self.current_power = power;
-                        self.historical_powers.push(power);
-                        self.historical_speeds.push(speed);
-                    }
+    fn render_ride_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            for tab in ControlTab::ALL {
+                let tab_label = egui::SelectableLabel::new(self.control_tab == tab, tab.label());
+                if ui
+                    .add_enabled(self.supports(tab), tab_label)
+                    .on_disabled_hover_text("Not supported by this trainer")
+                    .clicked()
+                {
+                    self.control_tab = tab;
                 }
             }
-        }
+            let spin_down = self.features.map_or(true, |f| f.spin_down());
+            if ui
+                .add_enabled(spin_down, egui::Button::new("Calibrate"))
+                .on_disabled_hover_text("Not supported by this trainer")
+                .clicked()
+            {
+                self.start_spin_down();
+            }
+        });
 
-        if let Some(ref mut rx) = self.discover_rx {
-            if let Ok(device) = rx.try_recv() {
-                let name = device.device.name().unwrap_or("UNKNOWN".into());
-                self.devices.insert(name, device);
+        ui.horizontal(|ui| {
+            let paused = self.paused_at.is_some();
+            if ui.add_enabled(paused, egui::Button::new("Start")).clicked() {
+                self.start_trainer();
             }
-        }
+            if ui.add_enabled(!paused, egui::Button::new("Pause")).clicked() {
+                self.pause_trainer();
+            }
+            if ui.button("Reset trainer").clicked() {
+                self.confirm_reset = Some(false);
+            }
+            if paused {
+                ui.label(RichText::new("Paused").color(Color32::YELLOW));
+            }
+        });
 
-        if let Some(ref mut rx) = self.connected_rx {
-            if let Ok(connected) = rx.try_recv() {
-                tracing::info!("Updated with connection");
-                self.connected_device = Some(connected);
-                self.connecting = false;
+        ui.horizontal(|ui| {
+            if let Some(status) = self.machine_status {
+                ui.label(format!("Status: {}", status));
             }
+            if let Some(status) = &self.training_status {
+                egui::Frame::none()
+                    .fill(ui.visuals().widgets.inactive.bg_fill)
+                    .inner_margin(4.0)
+                    .show(ui, |ui| ui.label(format!("Training phase: {}", status)));
+            }
+        });
+        if let Some(MachineStatus::StoppedBySafetyKey) = self.machine_status {
+            ui.label(
+                RichText::new("Safety key removed — please reattach")
+                    .size(18.0)
+                    .color(Color32::RED),
+            );
         }
-    }
 
-    fn start_discover(&mut self) {
-        let (tx, rx) = mpsc::channel(1024);
-        let (tx_stop, mut rx_stop) = oneshot::channel();
+        // A trainer-side workout phase we didn't start means some other app is driving it.
+        let program_elsewhere = self.workout.is_none()
+            && self.intervals.is_none()
+            && self.training_status.as_ref().is_some_and(TrainingStatus::is_program);
+        if program_elsewhere {
+            ui.label(
+                RichText::new("The trainer is running a workout from another app")
+                    .color(Color32::YELLOW),
+            );
+        }
 
-        let mut bt = self.bt.clone();
+        if !self.has_control {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Another app took control").color(Color32::YELLOW));
+                if ui.button("Request control").clicked() {
+                    self.request_control();
+                }
+            });
+        }
 
-        let _discover_task = self.rt.spawn(async move {
-            let mut device_stream = bt.discover_devices().await.unwrap();
+        ui.add_enabled_ui(self.has_control, |ui| match self.control_tab {
+            ControlTab::Power => self.render_power_controls(ui),
+            ControlTab::Resistance => self.render_resistance_controls(ui),
+            ControlTab::Simulation => self.render_simulation_controls(ui),
+        });
 
-            loop {
-                tokio::select! {
-                    Some(device) = device_stream.next() => {
-                        tracing::debug!("{:?}", device);
-                        tx.send(device).await.unwrap();
-                    }
-                    _ = &mut rx_stop => {
-                        tracing::info!("Received stop signal. Stopping the task.");
-                        break;
-                    }
+        if let Some(error) = &self.control_error {
+            ui.label(RichText::new(error).color(Color32::RED));
+        }
+
+        self.render_workout(ui);
+        self.render_intervals(ui);
+
+        ui.horizontal(|ui| {
+            if ui.button("Export FIT").clicked() {
+                if let Some(path) = self.export_session("FIT", "fit", fit::write_session) {
+                    self.fit_export = Some(path);
+                    self.upload_status = None;
+                    self.upload_url = None;
                 }
             }
+            if ui.button("Save TCX").clicked() {
+                self.export_session("TCX", "tcx", tcx::write);
+            }
+            #[cfg(feature = "serde")]
+            if ui.button("Save Session").clicked() {
+                self.save_session();
+            }
+            if ui.button("Export CSV").clicked() {
+                self.export_session("CSV", "csv", |path, records, devices| {
+                    Ok(csv::write_session(path, records, devices)?)
+                });
+            }
+            if self.final_tss.is_none() && ui.button("End Session").clicked() {
+                self.final_tss = Some(metrics::tss(
+                    self.session_duration(),
+                    self.np.normalized_power(),
+                    self.settings.ftp,
+                ));
+                #[cfg(feature = "serde")]
+                self.archive_session();
+            }
         });
 
-        self.discover_rx = Some(rx);
-        self.discover_stop = Some(tx_stop);
+        if self.fit_export.is_some() {
+            self.render_upload(ui);
+        }
+
+        ui.horizontal(|ui| {
+            let label = if self.show_bar_chart { "Line chart" } else { "Bar chart" };
+            if ui.button(label).clicked() {
+                self.show_bar_chart = !self.show_bar_chart;
+            }
+        });
+
+        if self.show_bar_chart {
+            let bars = recent(&self.historical_powers, self.settings.chart_window)
+                .map(|(secs, p)| Bar::new(*secs, *p as f64))
+                .collect();
+
+            let chart = BarChart::new(bars).name("Power (W)");
+
+            Plot::new("Power")
+                .legend(Legend::default())
+                .clamp_grid(true)
+                .y_axis_width(3)
+                .height(ui.available_height() / 2.0)
+                .show(ui, |plot_ui| plot_ui.bar_chart(chart))
+                .response;
+        } else {
+            self.render_combined_chart(ui);
+        }
+
+        if !self.historical_heart_rates.is_empty() {
+            let points: PlotPoints = self
+                .historical_heart_rates
+                .iter()
+                .map(|(secs, bpm)| [*secs, *bpm as f64])
+                .collect();
+
+            Plot::new("Heart Rate")
+                .legend(Legend::default())
+                .clamp_grid(true)
+                .y_axis_width(3)
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(points).color(Color32::RED).name("Heart rate"))
+                })
+                .response;
+        }
+
+        self.render_upcoming_steps(ui);
     }
 
-    fn stop_discover(&mut self) {
-        self.devices.clear();
+    fn render_upcoming_steps(&self, ui: &mut Ui) {
+        let (Some(workout), Some(started)) = (&self.workout, self.workout_started) else {
+            return;
+        };
+        let Some((current, _, remaining)) = workout.step_at(started.elapsed()) else {
+            return;
+        };
 
-        let tx = self.discover_stop.take();
+        let zones = PowerZones::new(self.settings.ftp);
+        let upcoming: Vec<_> = workout
+            .steps
+            .iter()
+            .skip(current)
+            .take(UPCOMING_STEPS)
+            .collect();
+        let total_secs: u32 = upcoming.iter().map(|step| step.duration_secs).sum();
 
-        if let Some(tx) = tx {
-            tx.send(()).unwrap();
+        let (rect, _) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width(), 30.0),
+            egui::Sense::hover(),
+        );
+        let painter = ui.painter();
+        let mut left = rect.left();
+        for (i, step) in upcoming.iter().enumerate() {
+            let width = rect.width() * step.duration_secs as f32 / total_secs.max(1) as f32;
+            let cell = egui::Rect::from_min_size(
+                egui::pos2(left, rect.top()),
+                egui::vec2(width, rect.height()),
+            )
+            .shrink(1.0);
+            left += width;
+
+            let zone = zones.zone_for(step.target_watts(self.settings.ftp));
+            painter.rect_filled(cell, 2.0, zone.color());
+
+            if i == 0 {
+                painter.rect_stroke(cell, 2.0, egui::Stroke::new(2.0, Color32::WHITE));
+                let secs = remaining.as_secs();
+                painter.text(
+                    cell.center(),
+                    egui::Align2::CENTER_CENTER,
+                    format!("{}:{:02}", secs / 60, secs % 60),
+                    egui::FontId::proportional(12.0),
+                    Color32::BLACK,
+                );
+            }
         }
     }
 
-    fn connect(&mut self, device: String, ctx: &egui::Context) {
-        tracing::info!("Connecting to {}", device);
+    fn render_zone_bar(&self, ui: &mut Ui, power: u16) {
+        let current = PowerZones::new(self.settings.ftp).zone_for(power);
 
-        self.connecting = true;
+        let (rect, _) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width(), 20.0),
+            egui::Sense::hover(),
+        );
+        let width = rect.width() / Zone::ALL.len() as f32;
+        let painter = ui.painter();
+        for (i, zone) in Zone::ALL.into_iter().enumerate() {
+            let cell = egui::Rect::from_min_size(
+                rect.min + egui::vec2(i as f32 * width, 0.0),
+                egui::vec2(width, rect.height()),
+            );
+            let color = if zone == current {
+                zone.color()
+            } else {
+                zone.color().gamma_multiply(0.25)
+            };
+            painter.rect_filled(cell.shrink(1.0), 2.0, color);
+            painter.text(
+                cell.center(),
+                egui::Align2::CENTER_CENTER,
+                format!("Z{}", zone.number()),
+                egui::FontId::proportional(12.0),
+                Color32::BLACK,
+            );
+        }
 
-        let (tx, rx) = oneshot::channel();
-        let device = self.devices[&device].clone();
-        let bt = self.bt.clone();
+        ui.label(format!("Z{} {}", current.number(), current.name()));
+    }
 
-        self.connected_rx = Some(rx);
-        let ctx = ctx.clone();
+    fn render_raw_packets(&self, ui: &mut Ui) {
+        egui::ScrollArea::vertical()
+            .max_height(200.0)
+            .show(ui, |ui| {
+                for (i, bytes) in self.raw_packets.iter().rev().enumerate() {
+                    let hex = bytes
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let flags = match ftms::indoor_bike_data_flags(bytes) {
+                        Some((flags, names)) => format!("{:#06x} [{}]", flags, names.join(", ")),
+                        None => "-".to_string(),
+                    };
+                    let decoded = match ftms::parse_indoor_bike_data(bytes) {
+                        Ok(sample) => decoded_fields(&sample).join(", "),
+                        Err(e) => e.to_string(),
+                    };
 
-        self.rt.spawn(async move {
-            let trainer = bt.connect(device, ctx).await.unwrap();
-            tracing::info!("Connection successful");
-            match tx.send(trainer) {
-                Ok(_) => {
-                    tracing::info!("SENT");
+                    egui::Grid::new(("raw_packet", i)).num_columns(2).show(ui, |ui| {
+                        ui.label("Raw: ");
+                        ui.monospace(hex);
+                        ui.end_row();
+                        ui.label("Flags: ");
+                        ui.label(flags);
+                        ui.end_row();
+                        ui.label("Decoded: ");
+                        ui.label(decoded);
+                        ui.end_row();
+                    });
+                    ui.separator();
                 }
-                Err(e) => {
-                    tracing::error!("ERROR {:?}", e);
+            });
+    }
+
+    fn render_power_controls(&mut self, ui: &mut Ui) {
+        let range = self.power_range;
+        let allowed = format!("{}–{} W in {} W steps", range.min, range.max, range.increment);
+
+        ui.horizontal(|ui| {
+            ui.label("Target power: ");
+            ui.add(
+                egui::DragValue::new(&mut self.target_power)
+                    .clamp_range(range.min..=range.max)
+                    .speed(range.increment)
+                    .suffix(" W"),
+            )
+            .on_hover_text(&allowed);
+            if ui.button("Set").clicked() {
+                self.target_power = range.snap(self.target_power);
+                self.set_target_power(self.target_power);
+            }
+            for delta in [-25, -5, 5, 25] {
+                if ui.button(format!("{:+}", delta)).clicked() {
+                    self.adjust_target_power(delta);
                 }
             }
+            if let Some(watts) = self.accepted_power {
+                let color = if self.target_pending {
+                    Color32::YELLOW
+                } else {
+                    ui.visuals().text_color()
+                };
+                ui.label(RichText::new(format!("(trainer: {} W)", watts)).color(color));
+            }
+            let applied = self.applied_target_power(self.target_power);
+            if self.erg.is_some() && applied != self.target_power {
+                ui.label(format!("sent as {} W", applied)).on_hover_text(
+                    "The target after the power bias and the minimum ERG power",
+                );
+            }
+            let bias = self.power_bias();
+            if !bias.is_zero() {
+                ui.label(RichText::new(format_bias(&bias)).color(Color32::YELLOW))
+                    .on_hover_text("Added to the target sent to the trainer, - and = adjust it");
+            }
         });
+
+        ui.horizontal(|ui| {
+            for (name, pct) in FTP_PRESETS {
+                let label = if self.settings.ftp > 0 {
+                    format!("{} · {} W", name, ftp_watts(self.settings.ftp, pct))
+                } else {
+                    format!("{} ({}% FTP)", name, pct)
+                };
+                if ui.button(label).clicked() {
+                    self.set_ftp_target(pct);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let label = if self.erg.is_some() { "ERG: on" } else { "ERG: off" };
+            if ui.button(label).clicked() {
+                self.toggle_erg();
+            }
+
+            if self.spiral_guard.is_active() {
+                ui.label(
+                    RichText::new("Low cadence, target eased until you spin back up")
+                        .color(Color32::YELLOW),
+                );
+            }
+
+            if self.erg.is_some() {
+                let slider = ui
+                    .add(
+                        egui::Slider::new(&mut self.target_power, range.min..=range.max)
+                            .step_by(range.increment as f64)
+                            .suffix(" W"),
+                    )
+                    .on_hover_text(&allowed);
+                if slider.drag_released() || (slider.changed() && !slider.dragged()) {
+                    if let Some(erg) = self.erg.as_mut() {
+                        erg.set_target(self.target_power);
+                    }
+                    self.set_target_power(self.target_power);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let label = if self.hr_control.is_some() {
+                "HR control: on"
+            } else {
+                "HR control: off"
+            };
+            if ui.button(label).clicked() {
+                self.toggle_hr_control();
+            }
+            ui.add(
+                egui::DragValue::new(&mut self.settings.hr_target_bpm)
+                    .clamp_range(90..=200)
+                    .suffix(" bpm"),
+            );
+            ui.label("between");
+            ui.add(
+                egui::DragValue::new(&mut self.settings.hr_min_power)
+                    .clamp_range(0..=self.settings.hr_max_power)
+                    .suffix(" W"),
+            );
+            ui.label("and");
+            ui.add(
+                egui::DragValue::new(&mut self.settings.hr_max_power)
+                    .clamp_range(self.settings.hr_min_power..=2000)
+                    .suffix(" W"),
+            );
+            if self.hr_control.is_some() && self.current.heart_rate.is_none() {
+                ui.label(RichText::new("Waiting for heart rate").color(Color32::YELLOW));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let label = if self.cadence_control.is_some() {
+                "Cadence control: on"
+            } else {
+                "Cadence control: off"
+            };
+            if ui
+                .button(label)
+                .on_hover_text("Target power follows cadence, edit the curve in the settings")
+                .clicked()
+            {
+                self.toggle_cadence_control();
+            }
+            if let Some(control) = &self.cadence_control {
+                if control.cadence_lost() {
+                    ui.label(RichText::new("No cadence, holding target").color(Color32::YELLOW));
+                } else if let Some(watts) = control.power() {
+                    ui.label(format!("{} W", watts));
+                }
+            }
+        });
+    }
+
+    fn render_resistance_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Resistance: ");
+            let range = self.resistance_range.unwrap_or_default();
+            let slider = ui.add(
+                egui::Slider::new(&mut self.target_resistance, range.min..=range.max)
+                    .step_by(range.increment as f64),
+            );
+            // Debounced in update_resistance so dragging doesn't flood the trainer with writes.
+            if slider.changed() {
+                self.resistance_changed = Some(Instant::now());
+                ui.ctx().request_repaint_after(RESISTANCE_DEBOUNCE);
+            }
+
+            let applied = match self.current.resistance {
+                Some(resistance) => resistance.to_string(),
+                None => "-".to_string(),
+            };
+            ui.label(format!("(applied: {})", applied));
+        });
+    }
+
+    fn render_simulation_controls(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        ui.horizontal(|ui| {
+            ui.label("Slope: ");
+            let range = self.inclination_range;
+            let slider = ui.add(
+                egui::Slider::new(&mut self.grade, range.min..=range.max)
+                    .step_by(range.increment.max(0.1) as f64)
+                    .suffix(" %"),
+            );
+            changed |= slider.drag_released() || (slider.changed() && !slider.dragged());
+
+            if let Some(requested) = self.clamped_grade {
+                ui.label(
+                    RichText::new(format!("{:.1} % is beyond the trainer's range", requested))
+                        .color(Color32::YELLOW),
+                );
+            }
+        });
+
+        if self.settings.virtual_gears > 1 {
+            ui.horizontal(|ui| {
+                if ui.button(RichText::new("−").size(24.0)).clicked() {
+                    self.shift_gear(-1);
+                }
+                let (gear, gears) = self.gear_number();
+                ui.label(RichText::new(format!("Gear {}/{}", gear, gears)).size(24.0).strong());
+                if ui.button(RichText::new("+").size(24.0)).clicked() {
+                    self.shift_gear(1);
+                }
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Trainer difficulty: ");
+            let difficulty = ui.add(
+                egui::Slider::new(&mut self.settings.trainer_difficulty_pct, 0.0..=100.0)
+                    .step_by(5.0)
+                    .suffix(" %"),
+            );
+            changed |= difficulty.drag_released()
+                || (difficulty.changed() && !difficulty.dragged());
+
+            if let Some(applied) = self.applied_grade {
+                ui.label(format!("Course {:.1} % → trainer {:.1} %", self.grade, applied));
+            }
+        });
+
+        ui.collapsing("Advanced", |ui| {
+            egui::Grid::new("simulation").num_columns(2).show(ui, |ui| {
+                ui.label("Wind speed");
+                let wind = ui.add(
+                    egui::DragValue::new(&mut self.wind_speed)
+                        .clamp_range(-30.0..=30.0)
+                        .speed(0.1)
+                        .suffix(" m/s"),
+                );
+                changed |= wind.drag_released() || (wind.changed() && !wind.dragged());
+                ui.end_row();
+
+                ui.label("Rolling resistance (Crr)");
+                let crr = ui.add(
+                    egui::DragValue::new(&mut self.crr)
+                        .clamp_range(0.0..=0.0255)
+                        .speed(0.0001)
+                        .max_decimals(4),
+                );
+                changed |= crr.drag_released() || (crr.changed() && !crr.dragged());
+                ui.end_row();
+
+                ui.label("Wind resistance (kg/m)");
+                let cw = ui.add(
+                    egui::DragValue::new(&mut self.cw)
+                        .clamp_range(0.0..=2.55)
+                        .speed(0.01),
+                );
+                changed |= cw.drag_released() || (cw.changed() && !cw.dragged());
+                ui.end_row();
+            });
+        });
+
+        if changed {
+            self.set_grade(self.grade);
+        }
+    }
+
+    fn render_workout(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Load Workout").clicked() {
+                self.load_workout();
+            }
+
+            let Some(workout) = &self.workout else {
+                return;
+            };
+            let elapsed = self
+                .workout_started
+                .map(|t| t.elapsed())
+                .unwrap_or(Duration::ZERO);
+
+            match workout.step_at(elapsed) {
+                Some((i, step, remaining)) => {
+                    let name = step.name.as_deref().unwrap_or("Interval");
+                    ui.label(format!("{} ({}/{})", name, i + 1, workout.steps.len()));
+                    let remaining = remaining.as_secs();
+                    ui.label(format!("{}:{:02}", remaining / 60, remaining % 60));
+                    ui.label(
+                        RichText::new(format!("{} W", step.target_watts(self.settings.ftp)))
+                            .color(Color32::GREEN),
+                    );
+                    // Keep the countdown ticking between trainer notifications.
+                    ui.ctx().request_repaint_after(Duration::from_secs(1));
+                }
+                None => {
+                    ui.label("Workout complete");
+                }
+            }
+        });
+    }
+
+    fn render_intervals(&mut self, ui: &mut Ui) {
+        if let Some(intervals) = &self.intervals {
+            let (label, color, until) = match intervals.current {
+                Some(TimerEvent::Work { round, until }) => {
+                    (format!("Work {}", round), Color32::RED, until)
+                }
+                Some(TimerEvent::Rest { round, until }) => {
+                    (format!("Rest {}", round), Color32::BLUE, until)
+                }
+                Some(TimerEvent::Done) | None => return,
+            };
+            let remaining = until.saturating_duration_since(Instant::now()).as_secs();
+
+            egui::Frame::none()
+                .fill(color.gamma_multiply(0.3))
+                .inner_margin(4.0)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new(label).size(24.0).color(color));
+                        ui.label(
+                            RichText::new(format!("{}:{:02}", remaining / 60, remaining % 60))
+                                .size(48.0)
+                                .color(color),
+                        );
+                        if ui.button("Stop").clicked() {
+                            self.intervals = None;
+                        }
+                    });
+                });
+            return;
+        }
+
+        ui.collapsing("Quick intervals", |ui| {
+            let setup = &mut self.interval_setup;
+            egui::Grid::new("intervals").num_columns(2).show(ui, |ui| {
+                ui.label("Work");
+                ui.add(
+                    egui::DragValue::new(&mut setup.work_secs)
+                        .clamp_range(5..=3600)
+                        .suffix(" s"),
+                );
+                ui.end_row();
+
+                ui.label("Rest");
+                ui.add(
+                    egui::DragValue::new(&mut setup.rest_secs)
+                        .clamp_range(5..=3600)
+                        .suffix(" s"),
+                );
+                ui.end_row();
+
+                ui.label("Repeats");
+                ui.add(egui::DragValue::new(&mut setup.repeats).clamp_range(1..=50));
+                ui.end_row();
+
+                ui.label("Set ERG targets");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut setup.set_power, "");
+                    ui.add_enabled(
+                        setup.set_power,
+                        egui::DragValue::new(&mut setup.work_watts)
+                            .clamp_range(0..=2000)
+                            .suffix(" W"),
+                    );
+                    ui.add_enabled(
+                        setup.set_power,
+                        egui::DragValue::new(&mut setup.rest_watts)
+                            .clamp_range(0..=2000)
+                            .suffix(" W"),
+                    );
+                });
+                ui.end_row();
+            });
+
+            if ui.button("Start Intervals").clicked() {
+                self.start_intervals(ui.ctx());
+            }
+        });
+    }
+
+    fn render_combined_chart(&self, ui: &mut Ui) {
+        let window = self.settings.chart_window;
+        let power_points: PlotPoints = recent(&self.historical_powers, window)
+            .map(|(secs, p)| [*secs, *p as f64])
+            .collect();
+        // Both series share one plot transform, so speed is scaled into the power range and the
+        // right axis labels undo the scaling.
+        let speed_points: PlotPoints = recent(&self.historical_speeds, window)
+            .map(|(secs, s)| [*secs, *s as f64 * SPEED_AXIS_SCALE])
+            .collect();
+
+        let power_axis = AxisHints::default().label("Power (W)");
+        let speed_axis = AxisHints::default()
+            .label("Speed (km/h)")
+            .formatter(|value, _, _| format!("{:.0}", value / SPEED_AXIS_SCALE))
+            .placement(HPlacement::Right);
+
+        Plot::new("History")
+            .legend(Legend::default())
+            .clamp_grid(true)
+            .x_axis_label("Elapsed (s)")
+            .custom_y_axes(vec![power_axis, speed_axis])
+            .height(ui.available_height() / 2.0)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(power_points).fill(0.0).name("Power (W)"));
+                plot_ui.line(
+                    Line::new(speed_points)
+                        .style(LineStyle::dashed_loose())
+                        .name("Speed (km/h)"),
+                );
+            });
+    }
+
+    fn render_settings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_settings;
+
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+                    ui.label("FTP");
+                    let ftp = ui.add(
+                        egui::DragValue::new(&mut self.settings.ftp)
+                            .clamp_range(50..=600)
+                            .suffix(" W"),
+                    );
+                    if std::mem::take(&mut self.focus_ftp) {
+                        ftp.request_focus();
+                    }
+                    ui.end_row();
+
+                    ui.label("UI scale");
+                    let scale = ui.add(
+                        egui::Slider::new(&mut self.settings.ui_scale, 0.5..=3.0).step_by(0.1),
+                    );
+                    // Rescaling mid-drag would move the slider out from under the pointer.
+                    if scale.drag_released() || (scale.changed() && !scale.dragged()) {
+                        self.apply_ui_scale(ctx);
+                    }
+                    ui.end_row();
+
+                    ui.label("Units");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.settings.units, Units::Metric, "Metric");
+                        ui.radio_value(&mut self.settings.units, Units::Imperial, "Imperial");
+                    });
+                    ui.end_row();
+
+                    ui.label("History samples");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.history_capacity)
+                            .clamp_range(60..=86400),
+                    );
+                    ui.end_row();
+
+                    ui.label("Chart samples");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.chart_window)
+                            .clamp_range(10..=3600),
+                    );
+                    ui.end_row();
+
+                    ui.label("Body weight").on_hover_text("Used for W/kg");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.weight_kg)
+                            .clamp_range(0.0..=200.0)
+                            .speed(0.5)
+                            .suffix(" kg"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Rider + bike weight");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.system_weight_kg)
+                            .clamp_range(30.0..=200.0)
+                            .speed(0.5)
+                            .suffix(" kg"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Hold missing values");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.hold_secs)
+                            .clamp_range(0..=30)
+                            .suffix(" s"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Filter power spikes");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.settings.spike_filter, "");
+                        ui.add_enabled(
+                            self.settings.spike_filter,
+                            egui::DragValue::new(&mut self.settings.spike_threshold)
+                                .clamp_range(100..=1500)
+                                .suffix(" W"),
+                        );
+                    });
+                    ui.end_row();
+
+                    ui.label("Power smoothing")
+                        .on_hover_text("Weight of each new reading, 1.0 shows raw power");
+                    ui.add(
+                        egui::Slider::new(&mut self.settings.power_smoothing, 0.0..=1.0)
+                            .step_by(0.05),
+                    );
+                    ui.end_row();
+
+                    ui.label("Smooth grade changes")
+                        .on_hover_text("Eases into steeper grades, descents apply at once");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.settings.grade_smoothing, "");
+                        ui.add_enabled(
+                            self.settings.grade_smoothing,
+                            egui::DragValue::new(&mut self.settings.max_grade_rate)
+                                .clamp_range(0.1..=5.0)
+                                .speed(0.1)
+                                .suffix(" %/s"),
+                        );
+                    });
+                    ui.end_row();
+
+                    ui.label("Virtual gears")
+                        .on_hover_text("Shift with + and - in simulation mode");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.settings.virtual_gears)
+                                .clamp_range(1..=30),
+                        );
+                        ui.add(
+                            egui::DragValue::new(&mut self.settings.gear_step_pct)
+                                .clamp_range(0.1..=2.0)
+                                .speed(0.1)
+                                .prefix("±")
+                                .suffix(" % per gear"),
+                        );
+                    });
+                    ui.end_row();
+
+                    ui.label("Wheel circumference")
+                        .on_hover_text("Turns wheel sensor revolutions into speed");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.wheel_circumference_mm)
+                            .clamp_range(1000..=2500)
+                            .suffix(" mm"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Low cadence protection").on_hover_text(
+                        "Eases the ERG target when cadence stays below the floor for 3 s",
+                    );
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.settings.spiral_guard, "");
+                        ui.add_enabled_ui(self.settings.spiral_guard, |ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut self.settings.spiral_cadence_floor)
+                                    .clamp_range(30.0..=90.0)
+                                    .suffix(" rpm"),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut self.settings.spiral_reduction_pct)
+                                    .clamp_range(5.0..=80.0)
+                                    .prefix("-")
+                                    .suffix(" %"),
+                            );
+                        });
+                    });
+                    ui.end_row();
+
+                    ui.label("Minimum ERG power").on_hover_text(
+                        "Lower targets are raised to this so the flywheel doesn't spin down",
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.min_erg_power)
+                            .clamp_range(0..=300)
+                            .suffix(" W"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Power bias").on_hover_text(
+                        "Corrects a trainer that reads off from your power meter, kept per trainer",
+                    );
+                    match self.settings.last_device.clone() {
+                        Some(id) => {
+                            let bias = self.settings.power_bias_mut(&id);
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut bias.watts)
+                                        .clamp_range(-MAX_BIAS_WATTS..=MAX_BIAS_WATTS)
+                                        .suffix(" W"),
+                                );
+                                ui.add(
+                                    egui::DragValue::new(&mut bias.pct)
+                                        .clamp_range(-MAX_BIAS_PCT..=MAX_BIAS_PCT)
+                                        .speed(0.1)
+                                        .suffix(" %"),
+                                );
+                            });
+                        }
+                        None => {
+                            ui.label("Connect a trainer first");
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Cadence to power").on_hover_text(
+                        "Target power for cadence control, interpolated between the points",
+                    );
+                    ui.vertical(|ui| {
+                        let mut remove = None;
+                        for (i, point) in self.settings.cadence_curve.iter_mut().enumerate() {
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut point.rpm)
+                                        .clamp_range(0.0..=200.0)
+                                        .suffix(" rpm"),
+                                );
+                                ui.add(
+                                    egui::DragValue::new(&mut point.watts)
+                                        .clamp_range(0..=2000)
+                                        .suffix(" W"),
+                                );
+                                if ui.small_button("✖").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                        }
+                        if let Some(i) = remove {
+                            self.settings.cadence_curve.remove(i);
+                        }
+                        if ui.small_button("Add point").clicked() {
+                            let last = self.settings.cadence_curve.last().copied();
+                            self.settings.cadence_curve.push(CadencePoint {
+                                rpm: last.map_or(90.0, |point| point.rpm + 10.0),
+                                watts: last.map_or(200, |point| point.watts),
+                            });
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Record raw data");
+                    ui.checkbox(&mut self.settings.record_raw, "");
+                    ui.end_row();
+
+                    ui.label("Reconnect attempts");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.reconnect_attempts)
+                            .clamp_range(0..=20),
+                    );
+                    ui.end_row();
+
+                    ui.label("Reconnect back-off");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.reconnect_backoff_secs)
+                            .clamp_range(1..=30)
+                            .suffix(" s"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Simulation update interval")
+                        .on_hover_text("Takes effect after restarting");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.simulation_interval_ms)
+                            .clamp_range(100..=5000)
+                            .speed(10)
+                            .suffix(" ms"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Strava client ID").on_hover_text(
+                        "From your API application at strava.com/settings/api",
+                    );
+                    ui.text_edit_singleline(&mut self.settings.strava_client_id);
+                    ui.end_row();
+
+                    ui.label("Strava client secret")
+                        .on_hover_text("Kept in the system keychain");
+                    ui.vertical(|ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.strava_client_secret)
+                                    .password(true),
+                            );
+                            let ready = !self.strava_client_secret.is_empty();
+                            if ui.add_enabled(ready, egui::Button::new("Save")).clicked() {
+                                let secret = std::mem::take(&mut self.strava_client_secret);
+                                let status = match strava::save_client_secret(&secret) {
+                                    Ok(()) => "Saved to the keychain".to_string(),
+                                    Err(e) => {
+                                        tracing::error!("Failed to save Strava secret: {}", e);
+                                        e.to_string()
+                                    }
+                                };
+                                self.strava_client_secret_status = Some(status);
+                            }
+                        });
+                        if let Some(status) = &self.strava_client_secret_status {
+                            ui.label(status);
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Garmin Connect login")
+                        .on_hover_text("The password is kept in the system keychain");
+                    ui.vertical(|ui| {
+                        ui.text_edit_singleline(&mut self.settings.garmin_username);
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.garmin_password)
+                                    .password(true)
+                                    .hint_text("Password"),
+                            );
+                            let ready = !self.settings.garmin_username.is_empty()
+                                && !self.garmin_password.is_empty();
+                            if ui.add_enabled(ready, egui::Button::new("Save")).clicked() {
+                                let password = std::mem::take(&mut self.garmin_password);
+                                let username = &self.settings.garmin_username;
+                                let status = match garmin::save_password(username, &password) {
+                                    Ok(()) => "Saved to the keychain".to_string(),
+                                    Err(e) => {
+                                        tracing::error!("Failed to save Garmin password: {}", e);
+                                        e.to_string()
+                                    }
+                                };
+                                self.garmin_password_status = Some(status);
+                            }
+                        });
+                        if let Some(status) = &self.garmin_password_status {
+                            ui.label(status);
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Raw data directory");
+                    ui.horizontal(|ui| {
+                        ui.label(self.settings.raw_log_dir.display().to_string());
+                        if ui.button("Choose").clicked() {
+                            if let Some(dir) = rfd::FileDialog::new()
+                                .set_directory(&self.settings.raw_log_dir)
+                                .pick_folder()
+                            {
+                                self.settings.raw_log_dir = dir;
+                            }
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Sessions directory");
+                    ui.horizontal(|ui| {
+                        ui.label(self.settings.sessions_dir.display().to_string());
+                        if ui.button("Choose").clicked() {
+                            if let Some(dir) = rfd::FileDialog::new()
+                                .set_directory(&self.settings.sessions_dir)
+                                .pick_folder()
+                            {
+                                self.settings.sessions_dir = dir;
+                            }
+                        }
+                    });
+                    ui.end_row();
+                });
+
+                if ui.button("Save").clicked() {
+                    if let Err(e) = self.settings.save() {
+                        tracing::error!("Failed to save settings: {}", e);
+                    }
+                }
+            });
+
+        self.show_settings = open;
+    }
+
+    fn render_spin_down_window(&mut self, ctx: &egui::Context) {
+        let Some(spin_down) = &self.spin_down else {
+            return;
+        };
+        let mut open = true;
+
+        egui::Window::new("Spin-down calibration")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| match spin_down {
+                SpinDown::Starting => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Requesting spin-down…");
+                    });
+                }
+                SpinDown::SpeedUp(target) => {
+                    let units = self.settings.units;
+                    ui.label(format!(
+                        "Speed up to between {} and {}",
+                        units.format_speed(target.low as f64),
+                        units.format_speed(target.high as f64)
+                    ));
+                    if let Some(speed) = self.current.speed {
+                        let color = if (target.low..=target.high).contains(&speed) {
+                            Color32::GREEN
+                        } else {
+                            Color32::YELLOW
+                        };
+                        ui.label(RichText::new(units.format_speed(speed as f64)).color(color));
+                    }
+                }
+                SpinDown::StopPedaling { since } => {
+                    ui.label("Stop pedaling and let the flywheel coast");
+                    ui.label(format!("{:.1} s", since.elapsed().as_secs_f32()));
+                    ui.ctx().request_repaint_after(Duration::from_millis(100));
+                }
+                SpinDown::Done(time) => {
+                    ui.label(RichText::new("Calibration complete").color(Color32::GREEN));
+                    if let Some(time) = time {
+                        ui.label(format!("Spin-down time: {:.1} s", time.as_secs_f32()));
+                    }
+                }
+                SpinDown::Failed(message) => {
+                    ui.label(RichText::new(message).color(Color32::RED));
+                }
+            });
+
+        if !open {
+            self.spin_down = None;
+        }
+    }
+
+    fn render_auto_connect(&mut self, ui: &mut Ui) {
+        let Some(auto_connect) = &mut self.auto_connect else {
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label(format!("Connecting to {}…", auto_connect.name));
+        });
+        if ui.button("Cancel").clicked() {
+            tracing::info!("Auto-connect cancelled");
+            if let Some(stop) = auto_connect.stop.take() {
+                let _ = stop.send(());
+            }
+            self.auto_connect = None;
+        }
+    }
+
+    fn render_setup_screen(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        ui.heading("Simple Trainer 0.1");
+
+        match self.discover_rx {
+            Some(_) => {
+                if ui.button("Stop Discovery").clicked() {
+                    self.stop_discover();
+                }
+            }
+            None => {
+                ui.horizontal(|ui| {
+                    ui.label("Name starts with: ");
+                    ui.text_edit_singleline(&mut self.name_prefix);
+                });
+                ui.checkbox(&mut self.scan_sensors, "Include heart rate and cadence sensors");
+                ui.checkbox(&mut self.settings.record_raw, "Record raw data");
+                if ui.button("Discover").clicked() {
+                    self.start_discover();
+                }
+                #[cfg(feature = "serde")]
+                if ui.button("Load Session").clicked() {
+                    self.load_session(ctx);
+                }
+            }
+        }
+
+        self.render_device_list(ui, ctx);
+    }
+
+    fn render_device_list(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        let mut devices: Vec<_> = self.devices.clone().into_iter().collect();
+        // Favorites first, then nearest devices, devices without a signal reading last.
+        devices.sort_by_key(|(_, device)| {
+            (
+                self.favorite(device).is_none(),
+                std::cmp::Reverse(device.rssi.unwrap_or(i16::MIN)),
+            )
+        });
+
+        if self.connecting {
+            ui.horizontal(|ui| {
+                ui.spinner();
+            });
+        } else {
+            // Favorites that aren't advertising right now are still listed.
+            for favorite in self.settings.favorites.clone() {
+                if devices
+                    .iter()
+                    .any(|(_, device)| trainer::device_address(device) == favorite.address)
+                {
+                    continue;
+                }
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("★").color(Color32::YELLOW));
+                    ui.label(RichText::new(&favorite.name).weak());
+                    ui.label(RichText::new("not in range").weak());
+                    if ui.small_button("✖").on_hover_text("Remove from favorites").clicked() {
+                        self.confirm_unfavorite = Some(favorite.clone());
+                    }
+                });
+            }
+
+            devices.iter().for_each(|(k, device)| {
+                if self
+                    .connected_devices
+                    .iter()
+                    .any(|handle| &handle.name == k)
+                {
+                    return;
+                }
+                ui.horizontal(|ui| {
+                    if self.favorite(device).is_some() {
+                        ui.label(RichText::new("★").color(Color32::YELLOW));
+                    }
+                    let kind = DeviceKind::of(device);
+                    if kind != DeviceKind::Trainer {
+                        ui.label(RichText::new(kind.label()).color(Color32::RED));
+                    }
+                    let link = ui.link(k.clone()).on_hover_text("Right-click for details");
+                    if link.clicked() {
+                        self.connect(k.clone(), ctx);
+                    }
+                    link.context_menu(|ui| self.render_device_details(ui, k, device));
+                    if let Some(rssi) = device.rssi {
+                        ui.label(RichText::new(format!("{} dBm", rssi)).color(rssi_color(rssi)));
+                    }
+                });
+            });
+        }
+    }
+
+    fn render_device_details(&mut self, ui: &mut Ui, name: &str, device: &AdvertisingDevice) {
+        let address = trainer::device_address(device);
+        ui.label(format!("{} {}", DeviceKind::of(device).label(), name));
+        ui.label(format!("Address: {}", address));
+        if let Some(rssi) = device.rssi {
+            ui.label(format!("Signal: {} dBm", rssi));
+        }
+
+        match self.favorite(device) {
+            Some(favorite) => {
+                if ui.button("Remove from Favorites").clicked() {
+                    self.confirm_unfavorite = Some(favorite.clone());
+                    ui.close_menu();
+                }
+            }
+            None => {
+                if ui.button("Add to Favorites").clicked() {
+                    self.settings.favorites.push(FavoriteDevice {
+                        name: name.to_string(),
+                        address,
+                    });
+                    self.save_settings();
+                    ui.close_menu();
+                }
+            }
+        }
+    }
+
+    fn render_unfavorite_window(&mut self, ctx: &egui::Context) {
+        let Some(favorite) = self.confirm_unfavorite.clone() else {
+            return;
+        };
+
+        egui::Window::new("Remove favorite")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Remove {} from favorites?", favorite.name));
+                ui.horizontal(|ui| {
+                    if ui.button("Remove").clicked() {
+                        self.settings.favorites.retain(|f| *f != favorite);
+                        self.save_settings();
+                        self.confirm_unfavorite = None;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_unfavorite = None;
+                    }
+                });
+            });
+    }
+
+    #[cfg(feature = "serde")]
+    fn render_history_window(&mut self, ctx: &egui::Context) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let mut open = true;
+
+        egui::Window::new("History")
+            .open(&mut open)
+            .default_size([640.0, 480.0])
+            .show(ctx, |ui| history.show(ui));
+
+        if !open {
+            self.history = None;
+        }
+    }
+
+    fn render_reset_window(&mut self, ctx: &egui::Context) {
+        let Some(mut clear_data) = self.confirm_reset else {
+            return;
+        };
+
+        egui::Window::new("Reset trainer")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Reset the trainer's elapsed time and distance counters?");
+                ui.checkbox(&mut clear_data, "Also clear ride data and restart the clock");
+                self.confirm_reset = Some(clear_data);
+
+                ui.horizontal(|ui| {
+                    if ui.button("Reset").clicked() {
+                        self.confirm_reset = None;
+                        self.reset_trainer(clear_data);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_reset = None;
+                    }
+                });
+            });
+    }
+
+    fn supports(&self, tab: ControlTab) -> bool {
+        // Resistance targets are meaningless without the trainer's range.
+        if tab == ControlTab::Resistance && self.resistance_range.is_none() {
+            return false;
+        }
+        let Some(features) = self.features else {
+            return true;
+        };
+        match tab {
+            ControlTab::Power => features.power_target(),
+            ControlTab::Resistance => features.resistance_target(),
+            ControlTab::Simulation => features.simulation(),
+        }
+    }
+
+    fn favorite(&self, device: &AdvertisingDevice) -> Option<&FavoriteDevice> {
+        let address = trainer::device_address(device);
+        self.settings
+            .favorites
+            .iter()
+            .find(|favorite| favorite.address == address)
+    }
+
+    fn apply_ui_scale(&self, ctx: &egui::Context) {
+        let scale = self.settings.ui_scale.clamp(0.5, 3.0);
+        ctx.set_pixels_per_point(self.native_pixels_per_point * scale);
+    }
+
+    fn save_settings(&self) {
+        if let Err(e) = self.settings.save() {
+            tracing::error!("Failed to save settings: {}", e);
+        }
+    }
+
+    fn render_devices_panel(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        ui.heading("Devices");
+
+        let mut disconnect = None;
+        for (i, handle) in self.connected_devices.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(handle.kind.label()).color(Color32::GREEN));
+                ui.label(&handle.name);
+                if let Some(&percent) = self.battery_levels.get(&handle.name) {
+                    let text = RichText::new(format!("🔋{}%", percent)).small();
+                    if percent < LOW_BATTERY_PCT {
+                        ui.label(text.color(Color32::RED));
+                    } else {
+                        ui.label(text);
+                    }
+                }
+                if ui.small_button("✖").on_hover_text("Disconnect").clicked() {
+                    disconnect = Some(i);
+                }
+                let stale = self
+                    .last_seen
+                    .get(&handle.name)
+                    .map_or(true, |seen| seen.elapsed() > STALE_AFTER);
+                if stale {
+                    ui.label(RichText::new("no data").color(Color32::YELLOW));
+                }
+            });
+            if !handle.info.is_empty() {
+                ui.push_id(i, |ui| {
+                    ui.collapsing("Details", |ui| {
+                        egui::Grid::new("device_info").num_columns(2).show(ui, |ui| {
+                            for (label, value) in handle.info.fields() {
+                                if let Some(value) = value {
+                                    ui.label(label);
+                                    ui.label(value);
+                                    ui.end_row();
+                                }
+                            }
+                        });
+                    });
+                });
+            }
+            if let (DeviceKind::Trainer, Some(features)) = (handle.kind, self.features) {
+                ui.collapsing("Features", |ui| {
+                    for name in features.machine_names() {
+                        ui.label(name);
+                    }
+                    ui.separator();
+                    for name in features.target_names() {
+                        ui.label(name);
+                    }
+                });
+            }
+        }
+        if let Some(i) = disconnect {
+            self.disconnect(i);
+        }
+
+        ui.collapsing("Add device", |ui| {
+            match self.discover_rx {
+                Some(_) => {
+                    if ui.button("Stop Discovery").clicked() {
+                        self.stop_discover();
+                    }
+                }
+                None => {
+                    if ui.button("Discover").clicked() {
+                        self.start_discover();
+                    }
+                }
+            }
+            self.render_device_list(ui, ctx);
+        });
+    }
+
+    fn handle_sample(&mut self, sample: BikeSample) {
+        let now = Instant::now();
+        let secs = match sample.elapsed_time {
+            Some(elapsed_time) => elapsed_time as f64,
+            None => self.session_secs(),
+        };
+        self.current.merge(&sample);
+
+        // Trainers may skip fields in some packets, hold the last value for a while
+        // rather than dropping to zero straight away.
+        let hold = Duration::from_secs(self.settings.hold_secs);
+        let held = [
+            hold_field(
+                &mut self.current.speed,
+                sample.speed.is_some(),
+                &mut self.speed_seen,
+                now,
+                hold,
+            ),
+            hold_field(
+                &mut self.current.cadence,
+                sample.cadence.is_some(),
+                &mut self.cadence_seen,
+                now,
+                hold,
+            ),
+            hold_field(
+                &mut self.current.power,
+                sample.power.is_some(),
+                &mut self.power_seen,
+                now,
+                hold,
+            ),
+        ]
+        .contains(&true);
+
+        let since_last = self.last_update.map(|last| now.duration_since(last));
+        self.last_update = Some(now);
+
+        match sample.distance {
+            Some(distance) => {
+                let origin = *self.distance_origin.get_or_insert(distance);
+                let distance = distance.saturating_sub(origin) as f64;
+                self.current_distance = self.distance_offset + distance;
+            }
+            None => {
+                // Trainer doesn't report distance, integrate speed instead.
+                if let (Some(delta), Some(speed)) = (since_last, self.current.speed) {
+                    self.integrated_distance.tick(speed, delta);
+                }
+                self.current_distance = self.integrated_distance.meters();
+            }
+        }
+
+        // Total energy counts from when the trainer powered on, report it per session.
+        if let Some(energy) = sample.energy {
+            let origin = *self.energy_origin.get_or_insert(energy);
+            let energy = energy.saturating_sub(origin);
+            self.current_energy = Some(self.energy_offset.saturating_add(energy));
+        }
+
+        let guarded = self.update_spiral_guard(now);
+        let capacity = self.settings.history_capacity;
+        if let Some(power) = sample.power {
+            // The controller works on the nominal scale, the bias is added when sending.
+            let nominal = self.power_bias().remove(power);
+            // Below the floor the trainer holds more than the target, nothing to correct.
+            let floored = self
+                .erg
+                .as_ref()
+                .map_or(false, |erg| erg.target() < self.settings.min_erg_power);
+            let erg = self.erg.as_mut().filter(|_| !guarded && !floored);
+            if let Some(watts) = erg.and_then(|erg| erg.tick(nominal)) {
+                self.set_target_power(watts);
+            }
+            push_history(&mut self.historical_powers, (secs, power), capacity);
+            self.np.push(now, power);
+            self.peaks.push(Duration::from_secs_f64(self.session_secs()), power);
+            if let Some(delta) = since_last {
+                self.work.tick(power, delta);
+            }
+            for (window, buffer) in ROLLING_WINDOWS.iter().zip(&mut self.rolling_powers) {
+                buffer.push_back((now, power));
+                while let Some((at, _)) = buffer.front() {
+                    if now.duration_since(*at) <= *window {
+                        break;
+                    }
+                    buffer.pop_front();
+                }
+            }
+        }
+        if let Some(speed) = sample.speed {
+            push_history(&mut self.historical_speeds, (secs, speed), capacity);
+        }
+        if let Some(cadence) = sample.cadence {
+            push_history(&mut self.historical_cadences, (secs, cadence), capacity);
+        }
+        // Some trainers bridge a heart rate strap into Indoor Bike Data.
+        if let Some(bpm) = sample.heart_rate {
+            push_history(&mut self.historical_heart_rates, (secs, bpm), capacity);
+            self.update_hr_control(bpm);
+        }
+
+        self.records.push(SessionRecord {
+            timestamp: sample.timestamp.unwrap_or_else(SystemTime::now),
+            elapsed: secs,
+            speed: self.current.speed.unwrap_or(0.0),
+            power: self.current.power.unwrap_or(0),
+            cadence: self.current.cadence,
+            heart_rate: self.current.heart_rate,
+            distance: self.current_distance,
+            held,
+        });
+        self.publish_dashboard();
+    }
+
+    fn start_dashboard(&mut self) {
+        let (tx, rx) = watch::channel(Dashboard::default());
+        let (updates_tx, _) = broadcast::channel(DASHBOARD_UPDATES_CAPACITY);
+        self.rt.spawn(dashboard::serve(rx, updates_tx.clone()));
+        self.dashboard_tx = Some(tx);
+        self.dashboard_updates = Some(updates_tx);
+    }
+
+    fn publish_dashboard(&self) {
+        let (Some(tx), Some(record)) = (&self.dashboard_tx, self.records.last()) else {
+            return;
+        };
+        let metrics = Metrics {
+            speed_kph: self.current.speed,
+            power_watts: self.current.power,
+            cadence_rpm: self.current.cadence,
+            heart_rate_bpm: self.current.heart_rate,
+            elapsed_seconds: record.elapsed,
+            distance_m: self.current_distance,
+        };
+        // Modified in place, cloning the whole history for every sample would add up.
+        tx.send_modify(|dashboard| {
+            dashboard.metrics = metrics;
+            dashboard.history.push(HistoryRow::from(record));
+        });
+    }
+
+    fn update_auto_connect(&mut self, ctx: &egui::Context) {
+        let Some(auto_connect) = &mut self.auto_connect else {
+            return;
+        };
+
+        match auto_connect.rx.try_recv() {
+            Ok(device) => {
+                let name = device
+                    .device
+                    .name()
+                    .unwrap_or_else(|_| auto_connect.name.clone());
+                self.auto_connect = None;
+                self.devices.insert(name.clone(), device);
+                self.connect(name, ctx);
+            }
+            Err(mpsc::error::TryRecvError::Empty) => {}
+            Err(mpsc::error::TryRecvError::Disconnected) => {
+                // The scan failed, fall back to manual discovery.
+                self.auto_connect = None;
+            }
+        }
+    }
+
+    fn update_discovery(&mut self) {
+        // Drain everything queued since the last frame, a packet can produce several updates.
+        while let Ok(TaggedUpdate { device, update }) = self.updates_rx.try_recv() {
+            #[cfg(feature = "serde")]
+            if let Some(tx) = &self.dashboard_updates {
+                match serde_json::to_string(&update) {
+                    // Fails when no WebSocket client is connected, which is fine.
+                    Ok(json) => {
+                        let _ = tx.send(json);
+                    }
+                    Err(e) => tracing::error!("Failed to serialize trainer update: {}", e),
+                }
+            }
+            let now = Instant::now();
+            self.session_start.get_or_insert(now);
+            if let Some(last) = self.last_data {
+                let gap = now.duration_since(last);
+                if gap > STOPWATCH_IDLE {
+                    self.idle_total += gap;
+                }
+            }
+            self.last_data = Some(now);
+            let source = match &device {
+                Some(name) => {
+                    let kind = self.device_kind(name);
+                    self.last_seen.insert(name.clone(), now);
+                    kind
+                }
+                None => None,
+            };
+
+            let offset = self
+                .connected_at
+                .map(|t| t.elapsed())
+                .unwrap_or(Duration::ZERO);
+            self.session.push(offset, update.clone());
+
+            match update {
+                TrainerUpdate::Sample(mut sample) => {
+                    // Dedicated sensors win over what the trainer bridges or estimates.
+                    if source == Some(DeviceKind::Trainer) {
+                        if self.has_device(DeviceKind::HeartRateMonitor) {
+                            sample.heart_rate = None;
+                        }
+                        if self.has_device(DeviceKind::CadenceSensor) {
+                            sample.cadence = None;
+                        }
+                    }
+
+                    self.sample_arrivals.push_back(now);
+                    while let Some(at) = self.sample_arrivals.front() {
+                        if now.duration_since(*at) <= RATE_WINDOW {
+                            break;
+                        }
+                        self.sample_arrivals.pop_front();
+                    }
+
+                    let samples = if self.settings.spike_filter {
+                        self.spike_filter.push(sample, self.settings.spike_threshold)
+                    } else {
+                        vec![sample]
+                    };
+                    self.power_filter.alpha = self.settings.power_smoothing;
+                    for mut sample in samples {
+                        sample.power = sample.power.map(|raw| self.power_filter.filter(raw));
+                        if self.paused_at.is_some() {
+                            // Keep the live readout going, but don't record while paused.
+                            self.current.merge(&sample);
+                        } else {
+                            self.handle_sample(sample);
+                        }
+                    }
+                }
+                TrainerUpdate::Features(features) => {
+                    tracing::info!("Trainer supports {:?}", features.target_names());
+                    self.features = Some(features);
+                    if !self.supports(self.control_tab) {
+                        self.control_tab = ControlTab::ALL
+                            .into_iter()
+                            .find(|tab| self.supports(*tab))
+                            .unwrap_or(ControlTab::Power);
+                    }
+                }
+                TrainerUpdate::InclinationRange(range) => {
+                    self.inclination_range = range;
+                    self.grade = range.clamp(self.grade).0;
+                }
+                TrainerUpdate::PowerRange(range) => {
+                    self.power_range = range;
+                    self.target_power = range.snap(self.target_power);
+                }
+                TrainerUpdate::ResistanceRange(range) => {
+                    let range = if range.is_usable() {
+                        range
+                    } else {
+                        tracing::warn!("Ignoring unusable resistance level range {:?}", range);
+                        ResistanceRange::default()
+                    };
+                    tracing::info!(
+                        "Resistance level range {:.1}..={:.1} in steps of {:.1}",
+                        range.min,
+                        range.max,
+                        range.increment
+                    );
+                    self.resistance_range = Some(range);
+                    self.target_resistance = range.snap(self.target_resistance);
+                }
+                TrainerUpdate::Cadence { rpm } => {
+                    let secs = self.session_secs();
+                    self.current.cadence = Some(rpm);
+                    self.cadence_seen = Some(Instant::now());
+                    push_history(
+                        &mut self.historical_cadences,
+                        (secs, rpm),
+                        self.settings.history_capacity,
+                    );
+                }
+                TrainerUpdate::ControlError(message) => {
+                    if let Some(SpinDown::Starting) = self.spin_down {
+                        self.spin_down = Some(SpinDown::Failed(message.clone()));
+                    }
+                    self.control_error = Some(message);
+                }
+                TrainerUpdate::SpinDownStarted(target) => {
+                    self.spin_down = Some(SpinDown::SpeedUp(target));
+                }
+                TrainerUpdate::Status(status) => {
+                    self.machine_status = Some(status);
+                    match status {
+                        MachineStatus::SpinDown(status) => self.update_spin_down(status),
+                        MachineStatus::Started => self.resume_ride(),
+                        MachineStatus::Paused
+                        | MachineStatus::Stopped
+                        | MachineStatus::StoppedBySafetyKey => self.pause_ride(),
+                        MachineStatus::TargetPowerChanged(watts) => {
+                            self.accepted_power = Some(watts);
+                            self.target_pending = false;
+                        }
+                        MachineStatus::ControlPermissionLost => {
+                            tracing::warn!("Lost control of the trainer");
+                            self.has_control = false;
+                            self.erg = None;
+                        }
+                        _ => {}
+                    }
+                }
+                TrainerUpdate::TrainingStatus(status) => {
+                    tracing::info!("Training status: {}", status);
+                    self.training_status = Some(status);
+                }
+                TrainerUpdate::ControlGranted => {
+                    self.has_control = true;
+                }
+                TrainerUpdate::Disconnected => {
+                    self.link_state = LinkState::Reconnecting;
+                }
+                TrainerUpdate::Reconnected => {
+                    self.link_state = LinkState::Connected;
+                }
+                TrainerUpdate::ReconnectFailed => {
+                    self.link_state = LinkState::Lost;
+                }
+                TrainerUpdate::RawIndoorBikeData(bytes) => {
+                    push_history(&mut self.raw_packets, bytes, RAW_PACKET_CAPACITY);
+                }
+                TrainerUpdate::HeartRate { bpm } => {
+                    let secs = self.session_secs();
+                    self.current.heart_rate = Some(bpm);
+                    self.update_hr_control(bpm);
+                    push_history(
+                        &mut self.historical_heart_rates,
+                        (secs, bpm),
+                        self.settings.history_capacity,
+                    );
+                }
+                TrainerUpdate::RrIntervals { millis } => {
+                    self.last_rr_interval = millis.last().copied();
+                }
+                TrainerUpdate::Battery { percent } => {
+                    if let Some(name) = device {
+                        self.battery_levels.insert(name, percent);
+                    }
+                }
+            }
+        }
+
+        if let Some(ref mut rx) = self.discover_rx {
+            // Devices keep advertising, each advertisement refreshes the signal strength.
+            while let Ok(mut device) = rx.try_recv() {
+                let name = device.device.name().unwrap_or("UNKNOWN".into());
+                if let Some(known) = self.devices.get(&name) {
+                    device.rssi = device.rssi.or(known.rssi);
+                }
+                self.devices.insert(name, device);
+            }
+        }
+
+        while let Ok(connection) = self.connections_rx.try_recv() {
+            self.connecting = false;
+            let Ok(handle) = connection else {
+                continue;
+            };
+
+            if handle.kind == DeviceKind::Trainer {
+                self.settings.last_device = self
+                    .devices
+                    .get(&handle.name)
+                    .map(trainer::device_address);
+                self.settings.last_device_name = Some(handle.name.clone());
+            }
+            self.connected_devices.push(handle);
+        }
+    }
+
+    /// Average power over the last `window`, read from the shortest buffer that covers it.
+    fn rolling_avg_power(&self, window: Duration) -> u16 {
+        let buffer = ROLLING_WINDOWS
+            .iter()
+            .zip(&self.rolling_powers)
+            .find(|(covered, _)| **covered >= window)
+            .map_or(&self.rolling_powers[2], |(_, buffer)| buffer);
+
+        metrics::rolling_avg_power(buffer, window, Instant::now())
+    }
+
+    /// Notifications per second over the last few seconds.
+    fn notification_rate(&self) -> f32 {
+        let (Some(first), Some(last)) = (self.sample_arrivals.front(), self.sample_arrivals.back())
+        else {
+            return 0.0;
+        };
+        if last.elapsed() > RATE_WINDOW {
+            return 0.0;
+        }
+
+        let span = last.duration_since(*first).as_secs_f32();
+        if span == 0.0 {
+            return 0.0;
+        }
+        (self.sample_arrivals.len() - 1) as f32 / span
+    }
+
+    fn freshness_color(&self) -> Color32 {
+        match self.sample_arrivals.back().map(|at| at.elapsed()) {
+            Some(age) if age <= STALE_AFTER => Color32::GREEN,
+            Some(age) if age <= LOST_AFTER => Color32::YELLOW,
+            _ => Color32::RED,
+        }
+    }
+
+    fn session_secs(&self) -> f64 {
+        self.session_duration().as_secs_f64()
+    }
+
+    /// Ride time, excluding the time spent paused.
+    fn session_duration(&self) -> Duration {
+        let paused = self.paused_total + self.paused_at.map_or(Duration::ZERO, |t| t.elapsed());
+        self.connected_at
+            .map(|t| t.elapsed().saturating_sub(paused))
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Time since the first update, not counting stretches without data from the trainer.
+    fn stopwatch(&self) -> Duration {
+        let (Some(start), Some(last)) = (self.session_start, self.last_data) else {
+            return Duration::ZERO;
+        };
+        let end = if last.elapsed() > STOPWATCH_IDLE {
+            last
+        } else {
+            Instant::now()
+        };
+        end.duration_since(start).saturating_sub(self.idle_total)
+    }
+
+    fn pause_ride(&mut self) {
+        if self.paused_at.is_none() {
+            tracing::info!("Ride paused");
+            self.paused_at = Some(Instant::now());
+        }
+    }
+
+    fn resume_ride(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            tracing::info!("Ride resumed");
+            self.paused_total += paused_at.elapsed();
+            // Don't integrate distance across the pause.
+            self.last_update = None;
+        }
+    }
+
+    fn start_discover(&mut self) {
+        let (tx, rx) = mpsc::channel(1024);
+        let (tx_stop, mut rx_stop) = oneshot::channel();
+
+        let mut bt = self.bt.clone();
+        let prefix = self.name_prefix.trim();
+        let options = ScanOptions {
+            include_sensors: self.scan_sensors,
+            name_prefix: (!prefix.is_empty()).then(|| prefix.to_string()),
+            device_id: None,
+        };
+
+        let _discover_task = self.rt.spawn(async move {
+            let mut device_stream = bt.discover_devices(options).await.unwrap();
+
+            loop {
+                tokio::select! {
+                    Some(device) = device_stream.next() => {
+                        tracing::debug!("{:?}", device);
+                        tx.send(device).await.unwrap();
+                    }
+                    _ = &mut rx_stop => {
+                        tracing::info!("Received stop signal. Stopping the task.");
+                        break;
+                    }
+                }
+            }
+        });
+
+        self.discover_rx = Some(rx);
+        self.discover_stop = Some(tx_stop);
+    }
+
+    fn stop_discover(&mut self) {
+        self.devices.clear();
+
+        let tx = self.discover_stop.take();
+
+        if let Some(tx) = tx {
+            tx.send(()).unwrap();
+        }
+    }
+
+    fn reset_session(&mut self) {
+        self.gear = 0;
+        self.applied_grade = None;
+        self.current_distance = 0.0;
+        self.distance_origin = None;
+        self.distance_offset = 0.0;
+        self.integrated_distance = DistanceAccumulator::default();
+        self.current_energy = None;
+        self.energy_origin = None;
+        self.energy_offset = 0;
+        self.last_update = None;
+        self.sample_arrivals.clear();
+        self.speed_seen = None;
+        self.cadence_seen = None;
+        self.power_seen = None;
+        self.spike_filter = SpikeFilter::default();
+        self.power_filter = PowerFilter::new(self.settings.power_smoothing);
+        self.control_error = None;
+        self.connected_at = Some(Instant::now());
+        self.paused_at = None;
+        self.paused_total = Duration::ZERO;
+        self.session_start = None;
+        self.last_data = None;
+        self.idle_total = Duration::ZERO;
+        self.records.clear();
+        if let Some(tx) = &self.dashboard_tx {
+            tx.send_replace(Dashboard::default());
+        }
+        self.session.clear();
+        self.historical_speeds.clear();
+        self.historical_powers.clear();
+        for buffer in &mut self.rolling_powers {
+            buffer.clear();
+        }
+        self.np = NpCalculator::default();
+        self.peaks = MeanMaxPower::default();
+        self.features = None;
+        self.resistance_range = None;
+        self.machine_status = None;
+        self.training_status = None;
+        self.accepted_power = None;
+        self.has_control = true;
+        self.work = EnergyAccumulator::default();
+        self.final_tss = None;
+        self.raw_packets.clear();
+        self.historical_cadences.clear();
+        self.historical_heart_rates.clear();
+        self.last_rr_interval = None;
+        self.current = BikeSample::default();
+    }
+
+    #[cfg(feature = "serde")]
+    fn save_session(&self) {
+        if self.session.is_empty() {
+            return;
+        }
+
+        let path = rfd::FileDialog::new()
+            .add_filter("Session", &["session"])
+            .set_directory(&self.settings.sessions_dir)
+            .set_file_name("ride.session")
+            .save_file();
+
+        if let Some(path) = path {
+            match self.session.save(&path) {
+                Ok(_) => tracing::info!("Saved session to {}", path.display()),
+                Err(e) => tracing::error!("Failed to save session: {}", e),
+            }
+        }
+    }
+
+    /// Saves the ended session to the sessions directory so it shows up in the history.
+    #[cfg(feature = "serde")]
+    fn archive_session(&self) {
+        if self.session.is_empty() {
+            return;
+        }
+
+        let dir = &self.settings.sessions_dir;
+        let path = dir.join(history::session_file_name());
+        let curve = history::power_curve(self.session.events());
+        let result = std::fs::create_dir_all(dir)
+            .map_err(bincode::Error::from)
+            .and_then(|_| self.session.save(&path))
+            .and_then(|_| history::save_power_curve(&path, &curve));
+        if let Err(e) = result {
+            tracing::error!("Failed to save session to {}: {}", dir.display(), e);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn toggle_history(&mut self) {
+        if self.history.take().is_some() {
+            return;
+        }
+
+        let sessions = match history::scan(&self.settings.sessions_dir, self.settings.ftp) {
+            Ok(sessions) => sessions,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::error!("Failed to read the sessions directory: {}", e);
+                Vec::new()
+            }
+        };
+        self.history = Some(HistoryScreen::new(sessions));
+    }
+
+    #[cfg(feature = "serde")]
+    fn load_session(&mut self, ctx: &egui::Context) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Session", &["session"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        tracing::info!("Replaying session {}", path.display());
+
+        self.reset_session();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let name = format!("Playback: {}", path.display());
+        self.connected_devices.push(DeviceHandle {
+            name: name.clone(),
+            kind: DeviceKind::Trainer,
+            info: DeviceInfo::default(),
+            stop: Some(stop_tx),
+        });
+
+        let updates_tx = self.updates_tx.clone();
+        let ctx = ctx.clone();
+
+        self.rt.spawn(async move {
+            let mut rx = match BT::playback(&path).await {
+                Ok(rx) => rx,
+                Err(e) => {
+                    tracing::error!("Failed to load session: {}", e);
+                    return;
+                }
+            };
+
+            loop {
+                tokio::select! {
+                    update = rx.recv() => match update {
+                        Some(update) => {
+                            let tagged = TaggedUpdate {
+                                device: Some(name.clone()),
+                                update,
+                            };
+                            if updates_tx.send(tagged).await.is_err() {
+                                break;
+                            }
+                            ctx.request_repaint();
+                        }
+                        None => break,
+                    },
+                    _ = &mut stop_rx => break,
+                }
+            }
+        });
+    }
+
+    fn connect(&mut self, name: String, ctx: &egui::Context) {
+        let device = self.devices[&name].clone();
+        let kind = DeviceKind::of(&device);
+        tracing::info!("Connecting to {} {}", kind.label(), name);
+
+        if kind == DeviceKind::Trainer {
+            self.reset_session();
+        }
+        self.open_connection(name, ctx);
+    }
+
+    /// Reconnects a trainer whose link was lost, keeping the ride recorded so far.
+    fn retry_trainer(&mut self, ctx: &egui::Context) {
+        let Some(index) = self
+            .connected_devices
+            .iter()
+            .position(|handle| handle.kind == DeviceKind::Trainer)
+        else {
+            return;
+        };
+        let name = self.connected_devices[index].name.clone();
+        if !self.devices.contains_key(&name) {
+            return;
+        }
+
+        self.disconnect(index);
+        self.open_connection(name, ctx);
+    }
+
+    fn open_connection(&mut self, name: String, ctx: &egui::Context) {
+        let device = self.devices[&name].clone();
+        let kind = DeviceKind::of(&device);
+
+        self.connecting = true;
+        if kind == DeviceKind::Trainer {
+            self.link_state = LinkState::Connected;
+        }
+
+        let bt = self.bt.clone();
+        let updates_tx = self.updates_tx.clone();
+        let connections_tx = self.connections_tx.clone();
+        let raw_log_dir = self
+            .settings
+            .record_raw
+            .then(|| self.settings.raw_log_dir.clone());
+        let reconnect = ReconnectPolicy {
+            attempts: self.settings.reconnect_attempts,
+            initial_backoff: Duration::from_secs(self.settings.reconnect_backoff_secs),
+        };
+        let system_weight_kg = self.settings.system_weight_kg;
+        let wheel_circumference_mm = self.settings.wheel_circumference_mm;
+        let ctx = ctx.clone();
+
+        self.rt.spawn(async move {
+            // Each device gets its own channel, fanned in to the app-wide one below.
+            let (device_tx, mut device_rx) = mpsc::channel(64);
+            let result = match kind {
+                DeviceKind::Trainer => {
+                    bt.connect(
+                        device.clone(),
+                        device_tx,
+                        ctx.clone(),
+                        raw_log_dir,
+                        reconnect,
+                        system_weight_kg,
+                    )
+                    .await
+                }
+                DeviceKind::HeartRateMonitor => {
+                    bt.connect_heart_rate(device.clone(), device_tx, ctx.clone())
+                        .await
+                }
+                DeviceKind::CadenceSensor => {
+                    bt.connect_cadence_sensor(
+                        device.clone(),
+                        device_tx,
+                        ctx.clone(),
+                        wheel_circumference_mm,
+                    )
+                    .await
+                }
+            };
+            if let Err(e) = result {
+                tracing::error!("Failed to connect to {}: {:?}", name, e);
+                let _ = connections_tx.send(Err(name));
+                ctx.request_repaint();
+                return;
+            }
+            tracing::info!("Connected to {}", name);
+            let info = trainer::read_device_info(&device).await;
+
+            let (stop_tx, mut stop_rx) = oneshot::channel();
+            let source = name.clone();
+            let handle = DeviceHandle {
+                name,
+                kind,
+                info,
+                stop: Some(stop_tx),
+            };
+            if connections_tx.send(Ok(handle)).is_err() {
+                return;
+            }
+            ctx.request_repaint();
+
+            loop {
+                tokio::select! {
+                    update = device_rx.recv() => match update {
+                        Some(update) => {
+                            let tagged = TaggedUpdate {
+                                device: Some(source.clone()),
+                                update,
+                            };
+                            if updates_tx.send(tagged).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = &mut stop_rx => break,
+                }
+            }
+
+            if let Err(e) = bt.disconnect(&device).await {
+                tracing::error!("Failed to disconnect: {:?}", e);
+            }
+        });
+    }
+
+    fn device_kind(&self, name: &str) -> Option<DeviceKind> {
+        self.connected_devices
+            .iter()
+            .find(|handle| handle.name == name)
+            .map(|handle| handle.kind)
+    }
+
+    fn has_device(&self, kind: DeviceKind) -> bool {
+        self.connected_devices.iter().any(|handle| handle.kind == kind)
+    }
+
+    fn disconnect(&mut self, index: usize) {
+        let mut handle = self.connected_devices.remove(index);
+        tracing::info!("Disconnecting {}", handle.name);
+        self.last_seen.remove(&handle.name);
+        self.battery_levels.remove(&handle.name);
+
+        if let Some(stop) = handle.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+
+    /// Returns where the session was written, `None` when cancelled or on failure.
+    fn export_session(
+        &self,
+        format: &str,
+        extension: &str,
+        write: fn(&Path, &[SessionRecord], &[DeviceInfo]) -> io::Result<()>,
+    ) -> Option<PathBuf> {
+        let path = rfd::FileDialog::new()
+            .add_filter(format, &[extension])
+            .set_file_name(format!("ride.{}", extension))
+            .save_file()?;
+
+        let devices: Vec<_> = self
+            .connected_devices
+            .iter()
+            .map(|handle| handle.info.clone())
+            .filter(|info| !info.is_empty())
+            .collect();
+        match write(&path, &self.records, &devices) {
+            Ok(_) => {
+                tracing::info!("Exported session to {}", path.display());
+                Some(path)
+            }
+            Err(e) => {
+                tracing::error!("Failed to export {} file: {}", format, e);
+                None
+            }
+        }
+    }
+
+    fn render_upload(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let idle = self.upload_rx.is_none();
+            if ui
+                .add_enabled(idle, egui::Button::new("Upload to Strava"))
+                .clicked()
+            {
+                self.upload_to_strava();
+            }
+            if ui
+                .add_enabled(idle, egui::Button::new("Upload to Garmin Connect"))
+                .clicked()
+            {
+                self.upload_to_garmin();
+            }
+            if let Some(status) = &self.upload_status {
+                ui.label(status);
+            }
+            if let Some(url) = &self.upload_url {
+                ui.hyperlink(url);
+            }
+        });
+    }
+
+    fn upload_to_strava(&mut self) {
+        let Some(path) = self.fit_export.clone() else {
+            return;
+        };
+        if self.settings.strava_client_id.is_empty() {
+            self.upload_status = Some("Add your Strava API client in the settings".to_string());
+            self.show_settings = true;
+            return;
+        }
+
+        let (tx, rx) = mpsc::channel(8);
+        let client_id = self.settings.strava_client_id.clone();
+        let expires_at = self.settings.strava_expires_at;
+        self.rt.spawn(strava::upload_fit(client_id, expires_at, path, tx));
+
+        self.upload_rx = Some(rx);
+        self.upload_status = Some("Starting upload".to_string());
+        self.upload_url = None;
+    }
+
+    fn upload_to_garmin(&mut self) {
+        let Some(path) = self.fit_export.clone() else {
+            return;
+        };
+        if self.settings.garmin_username.is_empty() {
+            self.upload_status = Some("Add your Garmin Connect login in the settings".to_string());
+            self.show_settings = true;
+            return;
+        }
+
+        let (tx, rx) = mpsc::channel(8);
+        let username = self.settings.garmin_username.clone();
+        self.rt.spawn(garmin::upload_fit(username, path, tx));
+
+        self.upload_rx = Some(rx);
+        self.upload_status = Some("Starting upload".to_string());
+        self.upload_url = None;
+    }
+
+    fn update_upload(&mut self, ctx: &egui::Context) {
+        let Some(rx) = self.upload_rx.as_mut() else {
+            return;
+        };
+        // Nothing else repaints while the rider is off in the browser.
+        ctx.request_repaint_after(Duration::from_millis(500));
+
+        loop {
+            match rx.try_recv() {
+                Ok(UploadEvent::Status(status)) => self.upload_status = Some(status),
+                Ok(UploadEvent::StravaTokens { expires_at }) => {
+                    self.settings.strava_expires_at = expires_at;
+                    // Keep the expiry in step with the tokens already in the keychain.
+                    if let Err(e) = self.settings.save() {
+                        tracing::error!("Failed to save settings: {}", e);
+                    }
+                }
+                Ok(UploadEvent::Done { url }) => {
+                    tracing::info!("Uploaded to {}", url);
+                    self.upload_status = Some("Uploaded".to_string());
+                    self.upload_url = Some(url);
+                }
+                Ok(UploadEvent::Failed(message)) => {
+                    tracing::error!("Upload failed: {}", message);
+                    self.upload_status = Some(format!("Upload failed: {}", message));
+                }
+                Err(mpsc::error::TryRecvError::Empty) => break,
+                Err(mpsc::error::TryRecvError::Disconnected) => {
+                    self.upload_rx = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn load_workout(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Workout", &["zwo", "yaml", "yml"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        self.start_workout(&path);
+    }
+
+    fn start_workout(&mut self, path: &Path) {
+        match Workout::load(path) {
+            Ok(workout) => {
+                tracing::info!("Starting workout {}", path.display());
+                self.workout = Some(workout);
+                self.workout_started = Some(Instant::now());
+                self.workout_step = None;
+            }
+            Err(e) => {
+                tracing::error!("Failed to load workout {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Loads a workout file dropped onto the window, whichever screen is showing.
+    fn update_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .find_map(|file| file.path.clone())
+        });
+        if let Some(path) = dropped {
+            self.start_workout(&path);
+        }
+    }
+
+    /// Pushes the next step's target when the workout moves on to it.
+    fn update_workout(&mut self) {
+        let (Some(workout), Some(started)) = (&self.workout, self.workout_started) else {
+            return;
+        };
+
+        let Some((i, step, _)) = workout.step_at(started.elapsed()) else {
+            self.workout_step = None;
+            return;
+        };
+        if self.workout_step == Some(i) {
+            return;
+        }
+
+        self.workout_step = Some(i);
+        let watts = step.target_watts(self.settings.ftp);
+        self.target_power = watts;
+        if let Some(erg) = self.erg.as_mut() {
+            erg.set_target(watts);
+            self.set_target_power(watts);
+        }
+    }
+
+    fn start_intervals(&mut self, ctx: &egui::Context) {
+        let setup = &self.interval_setup;
+        let timer = IntervalTimer {
+            work: Duration::from_secs(setup.work_secs),
+            rest: Duration::from_secs(setup.rest_secs),
+            repeats: setup.repeats,
+        };
+        tracing::info!("Starting intervals {:?}", timer);
+
+        let (tx, rx) = mpsc::channel(4);
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let ctx = ctx.clone();
+
+        self.rt.spawn(async move {
+            tokio::select! {
+                _ = timer.run(tx, ctx) => {}
+                _ = stop_rx => {}
+            }
+        });
+
+        self.intervals = Some(RunningIntervals {
+            rx,
+            _stop: stop_tx,
+            current: None,
+        });
+    }
+
+    fn update_intervals(&mut self) {
+        let Some(intervals) = &mut self.intervals else {
+            return;
+        };
+
+        let mut target = None;
+        while let Ok(event) = intervals.rx.try_recv() {
+            target = match event {
+                TimerEvent::Work { .. } => Some(self.interval_setup.work_watts),
+                TimerEvent::Rest { .. } => Some(self.interval_setup.rest_watts),
+                TimerEvent::Done => None,
+            };
+            intervals.current = Some(event);
+        }
+
+        if let Some(TimerEvent::Done) = intervals.current {
+            tracing::info!("Intervals done");
+            self.intervals = None;
+            return;
+        }
+
+        let Some(watts) = target.filter(|_| self.interval_setup.set_power) else {
+            return;
+        };
+        if let Some(erg) = self.erg.as_mut() {
+            self.target_power = watts;
+            erg.set_target(watts);
+            self.set_target_power(watts);
+        }
+    }
+
+    fn toggle_erg(&mut self) {
+        match self.erg.take() {
+            Some(erg) => {
+                tracing::info!("ERG mode disabled at {} W", erg.target());
+                self.hr_control = None;
+                self.cadence_control = None;
+            }
+            None => {
+                self.erg = Some(ErgController::new(self.target_power));
+                self.set_target_power(self.target_power);
+            }
+        }
+    }
+
+    fn toggle_hr_control(&mut self) {
+        if self.hr_control.take().is_some() {
+            tracing::info!("Heart rate control disabled");
+            return;
+        }
+
+        self.cadence_control = None;
+        let controller = HeartRateController::new(
+            self.settings.hr_target_bpm,
+            self.target_power,
+            self.settings.hr_min_power,
+            self.settings.hr_max_power,
+        );
+        tracing::info!("Holding {} bpm from {} W", controller.target_bpm, controller.power());
+        self.target_power = controller.power();
+        self.hr_control = Some(controller);
+        match self.erg.as_mut() {
+            Some(erg) => erg.set_target(self.target_power),
+            None => self.erg = Some(ErgController::new(self.target_power)),
+        }
+        self.set_target_power(self.target_power);
+    }
+
+    fn update_hr_control(&mut self, bpm: u8) {
+        if self.erg.is_none() {
+            // Something else left ERG mode, heart rate can't steer the trainer anymore.
+            self.hr_control = None;
+        }
+        let Some(controller) = self.hr_control.as_mut() else {
+            return;
+        };
+        controller.target_bpm = self.settings.hr_target_bpm;
+        controller.min_power = self.settings.hr_min_power;
+        controller.max_power = self.settings.hr_max_power;
+        let Some(watts) = controller.tick(Instant::now(), bpm) else {
+            return;
+        };
+
+        tracing::info!("Heart rate {} bpm, target power now {} W", bpm, watts);
+        self.target_power = watts;
+        if let Some(erg) = self.erg.as_mut() {
+            erg.set_target(watts);
+        }
+        self.set_target_power(watts);
+    }
+
+    fn set_ftp_target(&mut self, pct: u16) {
+        if self.settings.ftp == 0 {
+            self.show_settings = true;
+            self.focus_ftp = true;
+            return;
+        }
+
+        let watts = self.power_range.snap(ftp_watts(self.settings.ftp, pct));
+        self.hr_control = None;
+        self.cadence_control = None;
+        self.target_power = watts;
+        match self.erg.as_mut() {
+            Some(erg) => erg.set_target(watts),
+            None => self.erg = Some(ErgController::new(watts)),
+        }
+        self.set_target_power(watts);
+    }
+
+    fn toggle_cadence_control(&mut self) {
+        if self.cadence_control.take().is_some() {
+            tracing::info!("Cadence control disabled");
+            return;
+        }
+
+        tracing::info!("Target power now follows cadence");
+        self.hr_control = None;
+        self.cadence_control = Some(CadenceController::new());
+        if self.erg.is_none() {
+            self.erg = Some(ErgController::new(self.target_power));
+        }
+    }
+
+    fn update_cadence_control(&mut self, ctx: &egui::Context) {
+        if self.erg.is_none() {
+            self.cadence_control = None;
+        }
+        let Some(controller) = self.cadence_control.as_mut() else {
+            return;
+        };
+        // Keep ticking while cadence is missing, no samples means no repaints.
+        ctx.request_repaint_after(Duration::from_secs(1));
+        let watts = controller.tick(
+            Instant::now(),
+            self.current.cadence,
+            self.cadence_seen,
+            &self.settings.cadence_curve,
+        );
+        let Some(watts) = watts else {
+            return;
+        };
+
+        self.target_power = watts;
+        if let Some(erg) = self.erg.as_mut() {
+            erg.set_target(watts);
+        }
+        self.set_target_power(watts);
+    }
+
+    /// Eases the ERG target while cadence is collapsing, returns whether it is overridden.
+    fn update_spiral_guard(&mut self, now: Instant) -> bool {
+        let target = self.erg.as_ref().map(ErgController::target);
+        let Some(target) = target.filter(|_| self.settings.spiral_guard) else {
+            self.spiral_guard = SpiralGuard::new(
+                self.settings.spiral_cadence_floor,
+                self.settings.spiral_reduction_pct / 100.0,
+            );
+            return false;
+        };
+        let Some(cadence) = self.current.cadence else {
+            return self.spiral_guard.is_active();
+        };
+
+        self.spiral_guard.floor = self.settings.spiral_cadence_floor;
+        self.spiral_guard.reduction = self.settings.spiral_reduction_pct / 100.0;
+        let was_active = self.spiral_guard.is_active();
+        let Some(watts) = self.spiral_guard.tick(now, cadence, target) else {
+            return self.spiral_guard.is_active();
+        };
+
+        let active = self.spiral_guard.is_active();
+        if active && !was_active {
+            tracing::warn!("Cadence down to {:.0} rpm, easing target to {} W", cadence, watts);
+        } else if !active {
+            tracing::info!("Cadence recovered, back to {} W", watts);
+            // The controller's last command was overridden, start again from the target.
+            if let Some(erg) = self.erg.as_mut() {
+                erg.set_target(target);
+            }
+        }
+        self.set_target_power(watts);
+        active
+    }
+
+    /// Shifts the target by `delta` watts, showing it straight away while the trainer catches up.
+    fn adjust_target_power(&mut self, delta: i32) {
+        let watts = (self.target_power as i32 + delta).clamp(0, u16::MAX as i32) as u16;
+        self.target_power = self.power_range.snap(watts);
+        self.target_pending = true;
+        if let Some(erg) = self.erg.as_mut() {
+            erg.set_target(self.target_power);
+        }
+        self.set_target_power(self.target_power);
+    }
+
+    /// The bias saved for the connected trainer, zero when there is none.
+    fn power_bias(&self) -> PowerBias {
+        self.settings
+            .last_device
+            .as_deref()
+            .and_then(|id| self.settings.power_bias(id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn adjust_power_bias(&mut self, delta: i16) {
+        let Some(id) = self.settings.last_device.clone() else {
+            return;
+        };
+        let bias = self.settings.power_bias_mut(&id);
+        bias.watts = (bias.watts + delta).clamp(-MAX_BIAS_WATTS, MAX_BIAS_WATTS);
+        tracing::info!("Power bias now {} W", bias.watts);
+
+        if let Some(erg) = self.erg.as_mut() {
+            erg.set_target(self.target_power);
+            self.set_target_power(self.target_power);
+        }
+    }
+
+    fn update_bias_keys(&mut self, ctx: &egui::Context) {
+        if self.control_tab != ControlTab::Power || self.erg.is_none() {
+            return;
+        }
+        if ctx.memory(|memory| memory.focus().is_some()) {
+            return;
+        }
+
+        let (up, down) = ctx.input(|i| {
+            (
+                i.num_presses(egui::Key::PlusEquals),
+                i.num_presses(egui::Key::Minus),
+            )
+        });
+        if up != down {
+            self.adjust_power_bias(up as i16 - down as i16);
+        }
+    }
+
+    fn update_power_keys(&mut self, ctx: &egui::Context) {
+        // Leave the keys to whatever widget has focus, and only nudge power in ERG mode.
+        let focused = ctx.memory(|memory| memory.focus().is_some());
+        let held = ctx.input(|i| POWER_KEYS.into_iter().find(|(key, _)| i.key_down(*key)));
+        let Some((key, delta)) = held.filter(|_| self.erg.is_some() && !focused) else {
+            self.held_power_key = None;
+            return;
+        };
+
+        let now = Instant::now();
+        let next = match self.held_power_key {
+            Some((held, next)) if held == key => {
+                if now < next {
+                    ctx.request_repaint_after(next - now);
+                    return;
+                }
+                now + KEY_REPEAT_INTERVAL
+            }
+            _ => now + KEY_REPEAT_DELAY,
+        };
+        self.held_power_key = Some((key, next));
+        self.adjust_target_power(delta);
+        ctx.request_repaint_after(next - now);
+    }
+
+    fn update_resistance(&mut self) {
+        let Some(changed) = self.resistance_changed else {
+            return;
+        };
+        if changed.elapsed() < RESISTANCE_DEBOUNCE {
+            return;
+        }
+
+        self.resistance_changed = None;
+        self.set_target_resistance(self.target_resistance);
+    }
+
+    fn set_target_resistance(&mut self, level: f32) {
+        let Some(range) = self.resistance_range else {
+            tracing::warn!("Ignoring resistance level without a supported range");
+            return;
+        };
+        let level = range.snap(level);
+        if self.erg.take().is_some() {
+            tracing::info!("ERG mode disabled by resistance mode");
+        }
+        tracing::info!("Setting resistance level to {:.1}", level);
+
+        self.send_command(ControlCommand::TargetResistance(level));
+    }
+
+    fn request_control(&mut self) {
+        self.send_command(ControlCommand::RequestControl);
+    }
+
+    fn reset_trainer(&mut self, clear_data: bool) {
+        tracing::info!("Resetting trainer");
+
+        if clear_data {
+            self.reset_session();
+        } else {
+            // The trainer's counters restart from zero, carry on from what we have so far.
+            self.distance_offset = self.current_distance;
+            self.distance_origin = None;
+            self.energy_offset = self.current_energy.unwrap_or(0);
+            self.energy_origin = None;
+        }
+
+        self.send_command(ControlCommand::Reset);
+    }
+
+    fn start_trainer(&mut self) {
+        self.send_command(ControlCommand::Start);
+    }
+
+    fn pause_trainer(&mut self) {
+        self.send_command(ControlCommand::Pause);
+    }
+
+    fn start_spin_down(&mut self) {
+        tracing::info!("Starting spin-down calibration");
+
+        self.spin_down = Some(SpinDown::Starting);
+        self.send_command(ControlCommand::SpinDown);
+    }
+
+    fn update_spin_down(&mut self, status: SpinDownStatus) {
+        let Some(spin_down) = &self.spin_down else {
+            if status == SpinDownStatus::Requested {
+                tracing::info!("Trainer requested a spin-down calibration");
+            }
+            return;
+        };
+
+        self.spin_down = match (status, spin_down) {
+            (SpinDownStatus::StopPedaling, _) => Some(SpinDown::StopPedaling {
+                since: Instant::now(),
+            }),
+            (SpinDownStatus::Success, SpinDown::StopPedaling { since }) => {
+                Some(SpinDown::Done(Some(since.elapsed())))
+            }
+            (SpinDownStatus::Success, _) => Some(SpinDown::Done(None)),
+            (SpinDownStatus::Error, _) => Some(SpinDown::Failed(
+                "Spin-down failed, try again after warming up the trainer".to_string(),
+            )),
+            (SpinDownStatus::Requested, _) => return,
+        };
+    }
+
+    /// Current gear counted from 1 and the number of gears.
+    fn gear_number(&self) -> (i32, i32) {
+        let gears = self.settings.virtual_gears.max(1) as i32;
+        (self.gear + (gears + 1) / 2, gears)
+    }
+
+    fn shift_gear(&mut self, delta: i32) {
+        let gears = self.settings.virtual_gears.max(1) as i32;
+        let lowest = 1 - (gears + 1) / 2;
+        let gear = (self.gear + delta).clamp(lowest, lowest + gears - 1);
+        if gear == self.gear {
+            return;
+        }
+
+        self.gear = gear;
+        tracing::info!("Shifted to gear {}", self.gear_number().0);
+        if self.applied_grade.is_some() {
+            self.set_grade(self.grade);
+        }
+    }
+
+    fn update_gear_keys(&mut self, ctx: &egui::Context) {
+        if self.control_tab != ControlTab::Simulation || self.settings.virtual_gears < 2 {
+            return;
+        }
+        if ctx.memory(|memory| memory.focus().is_some()) {
+            return;
+        }
+
+        let (up, down) = ctx.input(|i| {
+            (
+                i.num_presses(egui::Key::PlusEquals),
+                i.num_presses(egui::Key::Minus),
+            )
+        });
+        if up != down {
+            self.shift_gear(up as i32 - down as i32);
+        }
+    }
+
+    fn set_grade(&mut self, grade: f32) {
+        // The course keeps its full grade, only what the trainer gets is scaled.
+        self.grade = grade;
+        let scaled = trainer::trainer_grade(
+            grade,
+            self.settings.trainer_difficulty_pct / 100.0,
+            self.gear as f32 * self.settings.gear_step_pct,
+        );
+        let (clamped, was_clamped) = self.inclination_range.clamp(scaled);
+        self.clamped_grade = was_clamped.then_some(scaled);
+        let grade = clamped;
+        self.applied_grade = Some(grade);
+
+        // The trainer leaves ERG mode once it receives simulation parameters.
+        if self.erg.take().is_some() {
+            tracing::info!("ERG mode disabled by simulation mode");
+        }
+        tracing::info!("Setting grade to {:.1} % (course {:.1} %)", grade, self.grade);
+
+        self.send_command(ControlCommand::Simulation {
+            grade,
+            wind_mps: self.wind_speed,
+            crr: self.crr,
+            cw: self.cw,
+            max_grade_rate: self
+                .settings
+                .grade_smoothing
+                .then_some(self.settings.max_grade_rate),
+        });
+    }
+
+    fn set_target_power(&mut self, watts: u16) {
+        // Trainers silently ignore targets outside their supported range.
+        let watts = self.power_range.snap(watts);
+        let applied = self.applied_target_power(watts);
+        if applied == watts {
+            tracing::info!("Setting target power to {} W", watts);
+        } else {
+            tracing::info!("Setting target power to {} W, sent as {} W", watts, applied);
+        }
+
+        self.send_command(ControlCommand::TargetPower(applied));
+    }
+
+    fn applied_target_power(&self, watts: u16) -> u16 {
+        applied_target(
+            watts,
+            &self.power_bias(),
+            self.settings.min_erg_power,
+            &self.power_range,
+        )
+    }
+
+    fn send_command(&mut self, command: ControlCommand) {
+        self.control_error = None;
+        if let Err(e) = self.commands_tx.try_send(command) {
+            tracing::error!("Failed to queue control command: {}", e);
+            self.control_error = Some("Too many pending trainer commands".to_string());
+        }
+    }
+}
+
+/// Scales every text style so body text is `body_size` points.
+fn enlarge_text(ctx: &egui::Context, body_size: f32) {
+    let mut style = (*ctx.style()).clone();
+    let scale = style
+        .text_styles
+        .get(&egui::TextStyle::Body)
+        .map_or(1.0, |font| body_size / font.size);
+    for font in style.text_styles.values_mut() {
+        font.size *= scale;
+    }
+    ctx.set_style(style);
+}
+
+fn visuals(dark_mode: bool) -> egui::Visuals {
+    if dark_mode {
+        egui::Visuals::dark()
+    } else {
+        egui::Visuals::light()
+    }
+}
+
+fn format_bias(bias: &PowerBias) -> String {
+    match (bias.watts, bias.pct) {
+        (watts, pct) if pct == 0.0 => format!("Bias {:+} W", watts),
+        (0, pct) => format!("Bias {:+.1} %", pct),
+        (watts, pct) => format!("Bias {:+} W {:+.1} %", watts, pct),
+    }
+}
+
+/// What the trainer is sent for a nominal target: biased, then raised to the `floor` and snapped
+/// to what the trainer supports. Recorded data and training load keep using its readings.
+fn applied_target(watts: u16, bias: &PowerBias, floor: u16, range: &PowerRange) -> u16 {
+    range.snap(bias.apply(watts).max(floor))
+}
+
+fn ftp_watts(ftp: u16, pct: u16) -> u16 {
+    (ftp as u32 * pct as u32 / 100) as u16
+}
+
+fn decoded_fields(sample: &BikeSample) -> Vec<String> {
+    let fields = [
+        ("speed", sample.speed.map(|v| v.to_string())),
+        ("avg speed", sample.average_speed.map(|v| v.to_string())),
+        ("cadence", sample.cadence.map(|v| v.to_string())),
+        ("power", sample.power.map(|v| v.to_string())),
+        ("avg power", sample.average_power.map(|v| v.to_string())),
+        ("distance", sample.distance.map(|v| v.to_string())),
+        ("resistance", sample.resistance.map(|v| v.to_string())),
+        ("energy", sample.energy.map(|v| v.to_string())),
+        ("heart rate", sample.heart_rate.map(|v| v.to_string())),
+        ("MET", sample.metabolic_equivalent.map(|v| v.to_string())),
+        ("elapsed", sample.elapsed_time.map(|v| v.to_string())),
+        ("remaining", sample.remaining_time.map(|v| v.to_string())),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("{}={}", name, value?)))
+        .collect()
+}
+
+/// Green above −60 dBm, yellow down to −80 dBm and red for anything weaker.
+fn rssi_color(rssi: i16) -> Color32 {
+    match rssi {
+        rssi if rssi > -60 => Color32::GREEN,
+        rssi if rssi >= -80 => Color32::YELLOW,
+        _ => Color32::RED,
+    }
+}
+
+/// Keeps a missing field at its last value for `hold`, then zeroes it. Returns whether the value
+/// was carried forward from an earlier sample.
+fn hold_field<T: Default>(
+    value: &mut Option<T>,
+    fresh: bool,
+    seen: &mut Option<Instant>,
+    now: Instant,
+    hold: Duration,
+) -> bool {
+    if fresh {
+        *seen = Some(now);
+        return false;
+    }
+
+    match *seen {
+        Some(at) if now.duration_since(at) <= hold => value.is_some(),
+        Some(_) => {
+            *value = Some(T::default());
+            *seen = None;
+            false
+        }
+        None => false,
+    }
+}
+
+fn push_history<T>(history: &mut VecDeque<T>, sample: T, capacity: usize) {
+    history.push_back(sample);
+    while history.len() > capacity {
+        history.pop_front();
+    }
+}
+
+fn recent<T>(history: &VecDeque<T>, window: usize) -> impl Iterator<Item = &T> {
+    history.iter().skip(history.len().saturating_sub(window))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bias(watts: i16, pct: f32) -> PowerBias {
+        PowerBias {
+            device_id: "trainer".to_string(),
+            watts,
+            pct,
+        }
+    }
+
+    const RANGE: PowerRange = PowerRange {
+        min: 0,
+        max: 2000,
+        increment: 1,
+    };
+
+    #[test]
+    fn applied_target_biases_the_target() {
+        assert_eq!(applied_target(200, &bias(0, 0.0), 0, &RANGE), 200);
+        assert_eq!(applied_target(200, &bias(-10, 0.0), 0, &RANGE), 190);
+        assert_eq!(applied_target(200, &bias(0, 5.0), 0, &RANGE), 210);
+    }
+
+    #[test]
+    fn applied_target_applies_bias_before_the_floor() {
+        // Biased down below the floor, the floor wins.
+        assert_eq!(applied_target(100, &bias(-30, 0.0), 80, &RANGE), 80);
+        // A target under the floor that the bias lifts above it keeps the bias.
+        assert_eq!(applied_target(60, &bias(30, 0.0), 80, &RANGE), 90);
+        // The floor itself is not biased.
+        assert_eq!(applied_target(0, &bias(-20, 0.0), 80, &RANGE), 80);
+    }
+
+    #[test]
+    fn applied_target_snaps_to_the_range() {
+        let range = PowerRange {
+            min: 25,
+            max: 1000,
+            increment: 5,
+        };
+        assert_eq!(applied_target(203, &bias(0, 0.0), 0, &range), 205);
+        assert_eq!(applied_target(1200, &bias(0, 0.0), 0, &range), 1000);
+        assert_eq!(applied_target(0, &bias(0, 0.0), 0, &range), 25);
+        assert_eq!(applied_target(200, &bias(-3, 0.0), 0, &range), 195);
     }
 }
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+use tokio::sync::{broadcast, watch};
+use tracing::{error, info, warn};
+
+use crate::export::SessionRecord;
+
+const ADDRESS: ([u8; 4], u16) = ([127, 0, 0, 1], 8080);
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct Metrics {
+    pub speed_kph: Option<f32>,
+    pub power_watts: Option<u16>,
+    pub cadence_rpm: Option<f32>,
+    pub heart_rate_bpm: Option<u8>,
+    pub elapsed_seconds: f64,
+    pub distance_m: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct HistoryRow {
+    elapsed_seconds: f64,
+    speed_kph: f32,
+    power_watts: u16,
+    cadence_rpm: Option<f32>,
+    heart_rate_bpm: Option<u8>,
+    distance_m: f64,
+}
+
+impl From<&SessionRecord> for HistoryRow {
+    fn from(record: &SessionRecord) -> Self {
+        Self {
+            elapsed_seconds: record.elapsed,
+            speed_kph: record.speed,
+            power_watts: record.power,
+            cadence_rpm: record.cadence,
+            heart_rate_bpm: record.heart_rate,
+            distance_m: record.distance,
+        }
+    }
+}
+
+/// What the app publishes to the web dashboard, updated in place with `send_modify`.
+#[derive(Debug, Default)]
+pub(crate) struct Dashboard {
+    pub metrics: Metrics,
+    pub history: Vec<HistoryRow>,
+}
+
+#[derive(Clone)]
+struct ServerState {
+    dashboard: watch::Receiver<Dashboard>,
+    // Trainer updates already serialized to JSON, shared by every WebSocket client.
+    updates: broadcast::Sender<String>,
+}
+
+/// Serves `/metrics`, `/history` and the `/ws` update stream on localhost until the app exits.
+pub(crate) async fn serve(
+    dashboard: watch::Receiver<Dashboard>,
+    updates: broadcast::Sender<String>,
+) {
+    let router = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/history", get(history))
+        .route("/ws", get(websocket))
+        .with_state(ServerState { dashboard, updates });
+
+    let addr = SocketAddr::from(ADDRESS);
+    let server = match axum::Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to start the web dashboard on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Web dashboard on http://{}", addr);
+    if let Err(e) = server.serve(router.into_make_service()).await {
+        error!("Web dashboard stopped: {}", e);
+    }
+}
+
+async fn metrics(State(state): State<ServerState>) -> Json<Metrics> {
+    Json(state.dashboard.borrow().metrics.clone())
+}
+
+async fn history(State(state): State<ServerState>) -> Json<Vec<HistoryRow>> {
+    Json(state.dashboard.borrow().history.clone())
+}
+
+async fn websocket(ws: WebSocketUpgrade, State(state): State<ServerState>) -> Response {
+    let rx = state.updates.subscribe();
+    ws.on_upgrade(move |socket| forward_updates(socket, rx))
+}
+
+async fn forward_updates(mut socket: WebSocket, mut rx: broadcast::Receiver<String>) {
+    loop {
+        match rx.recv().await {
+            Ok(json) => {
+                if let Err(_) = socket.send(Message::Text(json)).await {
+                    // The client went away.
+                    break;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("WebSocket client too slow, skipped {} updates", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use std::time::{Duration, Instant};
+
+use crate::settings::CadencePoint;
+
+const TOLERANCE_WATTS: i32 = 5;
+const GAIN: f32 = 0.25;
+const MAX_TARGET_WATTS: f32 = 2000.0;
+
+// Cadence has to stay under the floor this long before the target is eased.
+const LOW_CADENCE_GRACE: Duration = Duration::from_secs(3);
+// Cadence has to climb this far above the floor before the target comes back.
+const RECOVERY_HYSTERESIS_RPM: f32 = 10.0;
+const RAMP_BACK: Duration = Duration::from_secs(5);
+
+// Heart rate answers power changes with a lag of about 30 s, so adjust slowly and gently.
+const HR_CONTROL_PERIOD: Duration = Duration::from_secs(12);
+const HR_SMOOTHING: f32 = 0.1;
+const HR_DEADBAND_BPM: f32 = 2.0;
+const HR_GAIN_WATTS_PER_BPM: f32 = 1.0;
+const HR_MAX_STEP_WATTS: f32 = 10.0;
+
+const CADENCE_CONTROL_PERIOD: Duration = Duration::from_secs(1);
+// A cadence reading older than this counts as a dropout.
+const CADENCE_TIMEOUT: Duration = Duration::from_secs(2);
+// The last target is kept this long through a dropout before settling at the bottom of the curve.
+const CADENCE_HOLD: Duration = Duration::from_secs(5);
+
+pub(crate) struct ErgController {
+    target: u16,
+    commanded: u16,
+}
+
+impl ErgController {
+    pub fn new(target: u16) -> Self {
+        Self {
+            target,
+            commanded: target,
+        }
+    }
+
+    pub fn target(&self) -> u16 {
+        self.target
+    }
+
+    pub fn set_target(&mut self, target: u16) {
+        self.target = target;
+        self.commanded = target;
+    }
+
+    /// Returns the power to command next when `actual_power` has drifted more than
+    /// 5 W from the target, `None` while it is within tolerance.
+    pub fn tick(&mut self, actual_power: u16) -> Option<u16> {
+        let error = self.target as i32 - actual_power as i32;
+        if error.abs() <= TOLERANCE_WATTS {
+            return None;
+        }
+
+        let next = (self.commanded as f32 + error as f32 * GAIN)
+            .round()
+            .clamp(0.0, MAX_TARGET_WATTS) as u16;
+        if next == self.commanded {
+            return None;
+        }
+
+        self.commanded = next;
+        Some(next)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GuardState {
+    Normal,
+    Low { since: Instant },
+    Easing,
+    RampingBack { since: Instant },
+}
+
+/// Guards against the ERG "spiral of death": when cadence collapses the trainer raises
+/// resistance to hold the target, making it impossible to spin back up. After a few seconds
+/// below `floor` the target is cut by `reduction` (a fraction), then ramped back once cadence
+/// recovers.
+pub(crate) struct SpiralGuard {
+    pub floor: f32,
+    pub reduction: f32,
+    state: GuardState,
+    commanded: Option<u16>,
+}
+
+impl SpiralGuard {
+    pub fn new(floor: f32, reduction: f32) -> Self {
+        Self {
+            floor,
+            reduction,
+            state: GuardState::Normal,
+            commanded: None,
+        }
+    }
+
+    /// Whether the guard currently overrides the ERG target.
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, GuardState::Easing | GuardState::RampingBack { .. })
+    }
+
+    /// Returns the power to command next, `None` when nothing needs sending. Once the ramp back
+    /// completes `target` itself is returned so the caller can resume normal ERG control.
+    pub fn tick(&mut self, now: Instant, cadence: f32, target: u16) -> Option<u16> {
+        let eased = (target as f32 * (1.0 - self.reduction.clamp(0.0, 1.0))).round() as u16;
+
+        self.state = match self.state {
+            GuardState::Normal if cadence < self.floor => GuardState::Low { since: now },
+            GuardState::Normal => GuardState::Normal,
+            GuardState::Low { .. } if cadence >= self.floor => GuardState::Normal,
+            GuardState::Low { since } if now.duration_since(since) >= LOW_CADENCE_GRACE => {
+                GuardState::Easing
+            }
+            GuardState::Low { since } => GuardState::Low { since },
+            GuardState::Easing if cadence >= self.floor + RECOVERY_HYSTERESIS_RPM => {
+                GuardState::RampingBack { since: now }
+            }
+            GuardState::Easing => GuardState::Easing,
+            GuardState::RampingBack { .. } if cadence < self.floor => GuardState::Easing,
+            GuardState::RampingBack { since } if now.duration_since(since) >= RAMP_BACK => {
+                GuardState::Normal
+            }
+            GuardState::RampingBack { since } => GuardState::RampingBack { since },
+        };
+
+        let power = match self.state {
+            GuardState::Normal | GuardState::Low { .. } => {
+                // Hand back the full target once, after having overridden it.
+                return self.commanded.take().map(|_| target);
+            }
+            GuardState::Easing => eased,
+            GuardState::RampingBack { since } => {
+                let progress = now.duration_since(since).as_secs_f32() / RAMP_BACK.as_secs_f32();
+                (eased as f32 + (target as f32 - eased as f32) * progress.min(1.0)).round() as u16
+            }
+        };
+
+        if self.commanded == Some(power) {
+            return None;
+        }
+        self.commanded = Some(power);
+        Some(power)
+    }
+}
+
+/// Steers the ERG target to hold a heart rate. Every `HR_CONTROL_PERIOD` the smoothed heart rate
+/// is compared to the target and power nudged by a few watts. The controller works on the power
+/// itself rather than an accumulated error, so clamping it to `min_power..=max_power` keeps it
+/// from winding up while the rider can't reach the target.
+pub(crate) struct HeartRateController {
+    pub target_bpm: u8,
+    pub min_power: u16,
+    pub max_power: u16,
+    power: f32,
+    smoothed: Option<f32>,
+    last_adjusted: Option<Instant>,
+}
+
+impl HeartRateController {
+    pub fn new(target_bpm: u8, start_power: u16, min_power: u16, max_power: u16) -> Self {
+        Self {
+            target_bpm,
+            min_power,
+            max_power,
+            power: start_power.clamp(min_power, max_power.max(min_power)) as f32,
+            smoothed: None,
+            last_adjusted: None,
+        }
+    }
+
+    pub fn power(&self) -> u16 {
+        self.power.round() as u16
+    }
+
+    /// Feeds a heart rate reading, returns a new target power when one is due.
+    pub fn tick(&mut self, now: Instant, bpm: u8) -> Option<u16> {
+        let smoothed = match self.smoothed {
+            Some(smoothed) => smoothed + HR_SMOOTHING * (bpm as f32 - smoothed),
+            None => bpm as f32,
+        };
+        self.smoothed = Some(smoothed);
+
+        let last = *self.last_adjusted.get_or_insert(now);
+        if now.duration_since(last) < HR_CONTROL_PERIOD {
+            return None;
+        }
+        self.last_adjusted = Some(now);
+
+        let error = self.target_bpm as f32 - smoothed;
+        if error.abs() <= HR_DEADBAND_BPM {
+            return None;
+        }
+
+        let step = (error * HR_GAIN_WATTS_PER_BPM).clamp(-HR_MAX_STEP_WATTS, HR_MAX_STEP_WATTS);
+        let min = self.min_power as f32;
+        let next = (self.power + step).clamp(min, (self.max_power as f32).max(min));
+        if next.round() == self.power.round() {
+            return None;
+        }
+        self.power = next;
+        Some(self.power())
+    }
+}
+
+/// Interpolates linearly between the curve points, clamped to the first and last one.
+pub(crate) fn power_for_cadence(curve: &[CadencePoint], rpm: f32) -> Option<u16> {
+    let mut points = curve.to_vec();
+    points.sort_by(|a, b| a.rpm.total_cmp(&b.rpm));
+
+    let first = points.first()?;
+    if rpm <= first.rpm {
+        return Some(first.watts);
+    }
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if rpm <= b.rpm {
+            let t = (rpm - a.rpm) / (b.rpm - a.rpm).max(f32::EPSILON);
+            let watts = a.watts as f32 + t * (b.watts as f32 - a.watts as f32);
+            return Some(watts.round() as u16);
+        }
+    }
+    points.last().map(|point| point.watts)
+}
+
+/// Makes the target power follow cadence, like riding a fixed gear.
+pub(crate) struct CadenceController {
+    power: Option<u16>,
+    last_tick: Option<Instant>,
+    dropout_since: Option<Instant>,
+}
+
+impl CadenceController {
+    pub fn new() -> Self {
+        Self {
+            power: None,
+            last_tick: None,
+            dropout_since: None,
+        }
+    }
+
+    pub fn power(&self) -> Option<u16> {
+        self.power
+    }
+
+    pub fn cadence_lost(&self) -> bool {
+        self.dropout_since.is_some()
+    }
+
+    /// Returns a new target power at most once a second, `cadence_seen` is when the last
+    /// cadence reading arrived.
+    pub fn tick(
+        &mut self,
+        now: Instant,
+        cadence: Option<f32>,
+        cadence_seen: Option<Instant>,
+        curve: &[CadencePoint],
+    ) -> Option<u16> {
+        if let Some(last) = self.last_tick {
+            if now.duration_since(last) < CADENCE_CONTROL_PERIOD {
+                return None;
+            }
+        }
+        self.last_tick = Some(now);
+
+        let fresh = cadence_seen.map_or(false, |seen| now.duration_since(seen) < CADENCE_TIMEOUT);
+        let rpm = match cadence.filter(|_| fresh) {
+            Some(rpm) => {
+                self.dropout_since = None;
+                rpm
+            }
+            None => {
+                let since = *self.dropout_since.get_or_insert(now);
+                if now.duration_since(since) < CADENCE_HOLD {
+                    return None;
+                }
+                0.0
+            }
+        };
+
+        let watts = power_for_cadence(curve, rpm)?;
+        if self.power == Some(watts) {
+            return None;
+        }
+        self.power = Some(watts);
+        Some(watts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: u16 = 300;
+
+    /// Ticks `guard` once a second through a cadence trace, returning what it commands.
+    fn ride(guard: &mut SpiralGuard, start: Instant, cadences: &[f32]) -> Vec<Option<u16>> {
+        cadences
+            .iter()
+            .enumerate()
+            .map(|(secs, &rpm)| guard.tick(start + Duration::from_secs(secs as u64), rpm, TARGET))
+            .collect()
+    }
+
+    #[test]
+    fn spiral_guard_engages_after_the_grace_period() {
+        let mut guard = SpiralGuard::new(50.0, 0.3);
+        let commanded = ride(
+            &mut guard,
+            Instant::now(),
+            &[85.0, 80.0, 60.0, 45.0, 40.0, 35.0, 30.0, 30.0],
+        );
+        // Below the floor from the fourth second, eased to 70 % three seconds later.
+        assert_eq!(
+            commanded,
+            [None, None, None, None, None, None, Some(210), None]
+        );
+        assert!(guard.is_active());
+    }
+
+    #[test]
+    fn spiral_guard_ignores_a_short_dip() {
+        let mut guard = SpiralGuard::new(50.0, 0.3);
+        let commanded = ride(&mut guard, Instant::now(), &[85.0, 45.0, 40.0, 55.0, 85.0]);
+        assert!(commanded.iter().all(Option::is_none));
+        assert!(!guard.is_active());
+    }
+
+    #[test]
+    fn spiral_guard_ramps_back_once_cadence_recovers() {
+        let mut guard = SpiralGuard::new(50.0, 0.3);
+        let commanded = ride(
+            &mut guard,
+            Instant::now(),
+            &[
+                30.0, 30.0, 30.0, 30.0, // collapsed, eased at the third second
+                55.0, // above the floor, but not by the hysteresis yet
+                65.0, 70.0, 75.0, 80.0, 85.0, 85.0, 85.0,
+            ],
+        );
+        assert_eq!(
+            commanded,
+            [
+                None,
+                None,
+                None,
+                Some(210),
+                None,
+                None,
+                Some(228),
+                Some(246),
+                Some(264),
+                Some(282),
+                Some(TARGET),
+                None,
+            ]
+        );
+        assert!(!guard.is_active());
+    }
+
+    #[test]
+    fn spiral_guard_eases_again_when_cadence_drops_during_the_ramp() {
+        let mut guard = SpiralGuard::new(50.0, 0.3);
+        let commanded = ride(
+            &mut guard,
+            Instant::now(),
+            &[30.0, 30.0, 30.0, 30.0, 65.0, 70.0, 75.0, 40.0],
+        );
+        assert_eq!(
+            commanded,
+            [
+                None,
+                None,
+                None,
+                Some(210),
+                None,
+                Some(228),
+                Some(246),
+                Some(210)
+            ]
+        );
+        assert!(guard.is_active());
+    }
+
+    /// Rider whose heart rate settles at `resting + bpm_per_watt * power` with a 30 s lag.
+    struct Rider {
+        bpm: f32,
+        resting: f32,
+        bpm_per_watt: f32,
+    }
+
+    impl Rider {
+        const LAG_SECS: f32 = 30.0;
+
+        fn new() -> Self {
+            Self {
+                bpm: 60.0,
+                resting: 60.0,
+                bpm_per_watt: 0.4,
+            }
+        }
+
+        /// Advances the heart rate by one second riding at `power`.
+        fn ride(&mut self, power: u16) -> u8 {
+            let steady = self.resting + self.bpm_per_watt * power as f32;
+            self.bpm += (steady - self.bpm) / Self::LAG_SECS;
+            self.bpm.round() as u8
+        }
+    }
+
+    /// Rides `secs` seconds under `controller`, returning the power and heart rate each second.
+    fn hold_heart_rate(
+        controller: &mut HeartRateController,
+        rider: &mut Rider,
+        start: Instant,
+        secs: u64,
+    ) -> Vec<(u16, u8)> {
+        (0..secs)
+            .map(|sec| {
+                let bpm = rider.ride(controller.power());
+                controller.tick(start + Duration::from_secs(sec), bpm);
+                (controller.power(), bpm)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn heart_rate_controller_settles_on_the_target() {
+        // 145 bpm needs 212.5 W from this rider.
+        let mut controller = HeartRateController::new(145, 150, 100, 300);
+        let trace = hold_heart_rate(&mut controller, &mut Rider::new(), Instant::now(), 1800);
+
+        assert!(trace.iter().all(|&(power, _)| (100..=300).contains(&power)));
+        // Over the last ten minutes heart rate holds near the target and power stays put.
+        let settled = &trace[1200..];
+        assert!(settled.iter().all(|&(_, bpm)| bpm.abs_diff(145) <= 3));
+        let min = settled.iter().map(|&(power, _)| power).min().unwrap();
+        let max = settled.iter().map(|&(power, _)| power).max().unwrap();
+        assert!(
+            max - min <= 10,
+            "power swings between {} and {} W",
+            min,
+            max
+        );
+        assert!(min.abs_diff(212) <= 10);
+    }
+
+    #[test]
+    fn heart_rate_controller_does_not_wind_up_at_its_bounds() {
+        let start = Instant::now();
+        let mut rider = Rider::new();
+        // Out of reach, 300 W only gets this rider to 180 bpm.
+        let mut controller = HeartRateController::new(190, 150, 100, 300);
+        let trace = hold_heart_rate(&mut controller, &mut rider, start, 900);
+        assert!(trace.iter().all(|&(power, _)| power <= 300));
+        assert_eq!(controller.power(), 300);
+
+        // Lowering the target backs power off at the very next adjustment.
+        controller.target_bpm = 150;
+        let offset = Duration::from_secs(900);
+        let trace = hold_heart_rate(&mut controller, &mut rider, start + offset, 13);
+        assert!(trace.last().unwrap().0 < 300);
+    }
+}
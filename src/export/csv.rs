@@ -0,0 +1,56 @@
+use std::{fs::File, io::Write, path::Path};
+
+use serde::Serialize;
+
+use super::{SessionRecord, Summary};
+use crate::{metrics::PEAK_DURATIONS, trainer::DeviceInfo};
+
+pub(crate) use ::csv::Error as CsvError;
+
+#[derive(Serialize)]
+struct Row {
+    elapsed_seconds: f64,
+    speed_kph: f32,
+    power_watts: u16,
+    cadence_rpm: Option<f32>,
+    heart_rate_bpm: Option<u8>,
+    distance_m: f64,
+    held: bool,
+}
+
+pub(crate) fn write_session(
+    path: &Path,
+    records: &[SessionRecord],
+    devices: &[DeviceInfo],
+) -> Result<(), CsvError> {
+    let mut file = File::create(path)?;
+
+    // Session metadata goes in comment lines ahead of the header.
+    for device in devices {
+        writeln!(file, "# device: {}", device)?;
+    }
+    if let Some(summary) = Summary::from_records(records) {
+        for (duration, peak) in PEAK_DURATIONS.iter().zip(summary.peak_powers) {
+            if let Some(peak) = peak {
+                writeln!(file, "# peak_power_{}s_w: {}", duration.as_secs(), peak)?;
+            }
+        }
+    }
+
+    let mut writer = ::csv::Writer::from_writer(file);
+
+    for record in records {
+        writer.serialize(Row {
+            elapsed_seconds: record.elapsed,
+            speed_kph: record.speed,
+            power_watts: record.power,
+            cadence_rpm: record.cadence,
+            heart_rate_bpm: record.heart_rate,
+            distance_m: record.distance,
+            held: record.held,
+        })?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
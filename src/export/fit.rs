@@ -0,0 +1,433 @@
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{SessionRecord, Summary};
+use crate::{metrics::PEAK_DURATIONS, trainer::DeviceInfo};
+
+// Seconds between the Unix epoch and the FIT epoch (1989-12-31T00:00:00Z).
+const FIT_EPOCH_OFFSET: u64 = 631_065_600;
+
+const PROTOCOL_VERSION: u8 = 0x20;
+const PROFILE_VERSION: u16 = 2132;
+
+// FIT base types
+const ENUM: u8 = 0x00;
+const STRING: u8 = 0x07;
+const BYTE: u8 = 0x0D;
+const UINT8: u8 = 0x02;
+const UINT16: u8 = 0x84;
+const UINT32: u8 = 0x86;
+const UINT32Z: u8 = 0x8C;
+
+const INVALID_UINT8: u64 = 0xFF;
+const INVALID_UINT16: u64 = 0xFFFF;
+
+struct Message {
+    local: u8,
+    global: u16,
+    fields: &'static [(u8, u8)],
+}
+
+const FILE_ID: Message = Message {
+    local: 0,
+    global: 0,
+    // type, manufacturer, product, serial_number, time_created
+    fields: &[
+        (0, ENUM),
+        (1, UINT16),
+        (2, UINT16),
+        (3, UINT32Z),
+        (4, UINT32),
+    ],
+};
+
+const RECORD: Message = Message {
+    local: 1,
+    global: 20,
+    // timestamp, heart_rate, cadence, distance, speed, power
+    fields: &[
+        (253, UINT32),
+        (3, UINT8),
+        (4, UINT8),
+        (5, UINT32),
+        (6, UINT16),
+        (7, UINT16),
+    ],
+};
+
+const LAP: Message = Message {
+    local: 2,
+    global: 19,
+    // timestamp, event, event_type, start_time, total_elapsed_time, total_timer_time,
+    // total_distance, total_calories, avg_power, max_power
+    fields: &[
+        (253, UINT32),
+        (0, ENUM),
+        (1, ENUM),
+        (2, UINT32),
+        (7, UINT32),
+        (8, UINT32),
+        (9, UINT32),
+        (11, UINT16),
+        (19, UINT16),
+        (20, UINT16),
+    ],
+};
+
+const SESSION: Message = Message {
+    local: 3,
+    global: 18,
+    // timestamp, event, event_type, start_time, sport, sub_sport, total_elapsed_time,
+    // total_timer_time, total_distance, total_calories, avg_power, max_power,
+    // first_lap_index, num_laps
+    fields: &[
+        (253, UINT32),
+        (0, ENUM),
+        (1, ENUM),
+        (2, UINT32),
+        (5, ENUM),
+        (6, ENUM),
+        (7, UINT32),
+        (8, UINT32),
+        (9, UINT32),
+        (11, UINT16),
+        (20, UINT16),
+        (21, UINT16),
+        (25, UINT16),
+        (26, UINT16),
+    ],
+};
+
+const ACTIVITY: Message = Message {
+    local: 4,
+    global: 34,
+    // timestamp, total_timer_time, num_sessions, type, event, event_type
+    fields: &[
+        (253, UINT32),
+        (0, UINT32),
+        (1, UINT16),
+        (2, ENUM),
+        (3, ENUM),
+        (4, ENUM),
+    ],
+};
+
+// Peak powers have no profile field, the session carries them as developer fields described
+// by these messages.
+const DEVELOPER_DATA_ID: Message = Message {
+    local: 5,
+    global: 207,
+    // application_id, developer_data_index
+    fields: &[(1, BYTE), (3, UINT8)],
+};
+
+const FIELD_DESCRIPTION: Message = Message {
+    local: 6,
+    global: 206,
+    // developer_data_index, field_definition_number, fit_base_type_id, field_name, units
+    fields: &[(0, UINT8), (1, UINT8), (2, UINT8), (3, STRING), (8, STRING)],
+};
+
+const DEVICE_INFO: Message = Message {
+    local: 7,
+    global: 23,
+    // timestamp, device_index, manufacturer, serial_number, software_version,
+    // hardware_version, product_name
+    fields: &[
+        (253, UINT32),
+        (0, UINT8),
+        (2, UINT16),
+        (3, UINT32Z),
+        (5, UINT16),
+        (6, UINT8),
+        (27, STRING),
+    ],
+};
+
+const APPLICATION_ID: [u8; 16] = *b"bike-trainer-rs\0";
+// Byte arrays and strings are written at this fixed size, zero padded.
+const TEXT_SIZE: usize = 16;
+
+// Profile enum values
+const FILE_ACTIVITY: u64 = 4;
+const MANUFACTURER_DEVELOPMENT: u64 = 255;
+const EVENT_SESSION: u64 = 8;
+const EVENT_LAP: u64 = 9;
+const EVENT_ACTIVITY: u64 = 26;
+const EVENT_TYPE_STOP: u64 = 1;
+const SPORT_CYCLING: u64 = 2;
+const SUB_SPORT_INDOOR_CYCLING: u64 = 6;
+const ACTIVITY_MANUAL: u64 = 0;
+
+pub(crate) fn write_session(
+    path: &Path,
+    records: &[SessionRecord],
+    devices: &[DeviceInfo],
+) -> io::Result<()> {
+    let summary = Summary::from_records(records)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no records to export"))?;
+
+    fs::write(path, encode(records, &summary, devices))
+}
+
+fn encode(records: &[SessionRecord], summary: &Summary, devices: &[DeviceInfo]) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    let start = fit_timestamp(summary.start);
+    let end = fit_timestamp(summary.end);
+    let total_time = (summary.total_secs * 1000.0).round() as u64;
+    let total_distance = (summary.distance * 100.0).round() as u64;
+
+    encoder.define(&FILE_ID);
+    encoder.data(
+        &FILE_ID,
+        &[FILE_ACTIVITY, MANUFACTURER_DEVELOPMENT, 0, 1, start],
+    );
+
+    if !devices.is_empty() {
+        encoder.define(&DEVICE_INFO);
+    }
+    for (index, device) in devices.iter().enumerate() {
+        // Index 0 is the creator, the app itself. Values the profile wants as numbers are only
+        // kept when the device reports them as such.
+        let serial = device
+            .serial_number
+            .as_deref()
+            .and_then(|serial| serial.parse::<u32>().ok())
+            .unwrap_or(0);
+        let software = device
+            .firmware_revision
+            .as_deref()
+            .and_then(|firmware| firmware.parse::<f32>().ok())
+            .map_or(INVALID_UINT16, |version| (version * 100.0).round() as u64);
+        let hardware = device
+            .hardware_revision
+            .as_deref()
+            .and_then(|hardware| hardware.parse::<u8>().ok())
+            .map_or(INVALID_UINT8, u64::from);
+        let name = [device.manufacturer.as_deref(), device.model.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        encoder.data_bytes(
+            &DEVICE_INFO,
+            &[
+                &(start as u32).to_le_bytes(),
+                &[index as u8 + 1],
+                &(INVALID_UINT16 as u16).to_le_bytes(),
+                &serial.to_le_bytes(),
+                &(software.min(INVALID_UINT16) as u16).to_le_bytes(),
+                &[hardware as u8],
+                name.as_bytes(),
+            ],
+        );
+    }
+
+    encoder.define(&RECORD);
+    for record in records {
+        let speed_mm_per_s = record.speed as f64 / 3.6 * 1000.0;
+        encoder.data(
+            &RECORD,
+            &[
+                fit_timestamp(record.timestamp),
+                record.heart_rate.map_or(INVALID_UINT8, u64::from),
+                record
+                    .cadence
+                    .map_or(INVALID_UINT8, |rpm| rpm.round().min(254.0) as u64),
+                (record.distance * 100.0).round() as u64,
+                speed_mm_per_s.round() as u64,
+                record.power as u64,
+            ],
+        );
+    }
+
+    encoder.define(&LAP);
+    encoder.data(
+        &LAP,
+        &[
+            end,
+            EVENT_LAP,
+            EVENT_TYPE_STOP,
+            start,
+            total_time,
+            total_time,
+            total_distance,
+            summary.calories as u64,
+            summary.avg_power as u64,
+            summary.max_power as u64,
+        ],
+    );
+
+    encoder.define(&DEVELOPER_DATA_ID);
+    encoder.data_bytes(&DEVELOPER_DATA_ID, &[&APPLICATION_ID, &[0]]);
+    encoder.define(&FIELD_DESCRIPTION);
+    for (number, duration) in PEAK_DURATIONS.iter().enumerate() {
+        let name = format!("peak_power_{}s", duration.as_secs());
+        encoder.data_bytes(
+            &FIELD_DESCRIPTION,
+            &[&[0], &[number as u8], &[UINT16], name.as_bytes(), b"W"],
+        );
+    }
+
+    encoder.define_with_developer_fields(&SESSION, PEAK_DURATIONS.len());
+    encoder.data(
+        &SESSION,
+        &[
+            end,
+            EVENT_SESSION,
+            EVENT_TYPE_STOP,
+            start,
+            SPORT_CYCLING,
+            SUB_SPORT_INDOOR_CYCLING,
+            total_time,
+            total_time,
+            total_distance,
+            summary.calories as u64,
+            summary.avg_power as u64,
+            summary.max_power as u64,
+            0,
+            1,
+        ],
+    );
+    encoder.developer_values(
+        &summary
+            .peak_powers
+            .map(|peak| peak.map_or(INVALID_UINT16, u64::from)),
+    );
+
+    encoder.define(&ACTIVITY);
+    encoder.data(
+        &ACTIVITY,
+        &[
+            end,
+            total_time,
+            1,
+            ACTIVITY_MANUAL,
+            EVENT_ACTIVITY,
+            EVENT_TYPE_STOP,
+        ],
+    );
+
+    encoder.finish()
+}
+
+#[derive(Default)]
+struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    fn define(&mut self, message: &Message) {
+        self.define_with_developer_fields(message, 0);
+    }
+
+    /// Defines `message` followed by `count` UINT16 developer fields of developer data index 0,
+    /// numbered from 0. Their values go in through `developer_values` after each `data`.
+    fn define_with_developer_fields(&mut self, message: &Message, count: usize) {
+        let developer_flag = if count > 0 { 0x20 } else { 0 };
+        self.data.push(0x40 | developer_flag | message.local);
+        self.data.push(0); // reserved
+        self.data.push(0); // little endian
+        self.data.extend_from_slice(&message.global.to_le_bytes());
+        self.data.push(message.fields.len() as u8);
+        for &(number, base_type) in message.fields {
+            self.data
+                .extend_from_slice(&[number, base_type_size(base_type), base_type]);
+        }
+        if count > 0 {
+            self.data.push(count as u8);
+            for number in 0..count as u8 {
+                self.data.extend_from_slice(&[number, 2, 0]);
+            }
+        }
+    }
+
+    fn data(&mut self, message: &Message, values: &[u64]) {
+        debug_assert_eq!(message.fields.len(), values.len());
+
+        self.data.push(message.local);
+        for (&(_, base_type), value) in message.fields.iter().zip(values) {
+            let size = base_type_size(base_type) as usize;
+            // Saturate to the field's invalid value rather than wrapping around.
+            let max = (1u64 << (size * 8)) - 1;
+            self.data
+                .extend_from_slice(&(*value).min(max).to_le_bytes()[..size]);
+        }
+    }
+
+    /// Like `data`, for messages with byte array or string fields given as raw bytes.
+    fn data_bytes(&mut self, message: &Message, values: &[&[u8]]) {
+        debug_assert_eq!(message.fields.len(), values.len());
+
+        self.data.push(message.local);
+        for (&(_, base_type), value) in message.fields.iter().zip(values) {
+            let size = base_type_size(base_type) as usize;
+            // Strings keep at least one terminating zero.
+            let len = match base_type {
+                STRING => value.len().min(size - 1),
+                _ => value.len().min(size),
+            };
+            self.data.extend_from_slice(&value[..len]);
+            self.data.resize(self.data.len() + size - len, 0);
+        }
+    }
+
+    fn developer_values(&mut self, values: &[u64]) {
+        for value in values {
+            self.data
+                .extend_from_slice(&((*value).min(INVALID_UINT16) as u16).to_le_bytes());
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut file = Vec::with_capacity(self.data.len() + 16);
+        file.push(14);
+        file.push(PROTOCOL_VERSION);
+        file.extend_from_slice(&PROFILE_VERSION.to_le_bytes());
+        file.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        file.extend_from_slice(b".FIT");
+        let header_crc = crc(&file);
+        file.extend_from_slice(&header_crc.to_le_bytes());
+
+        file.extend_from_slice(&self.data);
+        let file_crc = crc(&file);
+        file.extend_from_slice(&file_crc.to_le_bytes());
+        file
+    }
+}
+
+fn base_type_size(base_type: u8) -> u8 {
+    match base_type {
+        ENUM | UINT8 => 1,
+        UINT16 => 2,
+        UINT32 | UINT32Z => 4,
+        STRING | BYTE => TEXT_SIZE as u8,
+        _ => unreachable!("unsupported base type {:#04x}", base_type),
+    }
+}
+
+fn fit_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs().saturating_sub(FIT_EPOCH_OFFSET))
+        .unwrap_or(0)
+}
+
+fn crc(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+
+    bytes.iter().fold(0, |mut crc, &byte| {
+        let tmp = TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ TABLE[(byte & 0xF) as usize];
+
+        let tmp = TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc ^ tmp ^ TABLE[(byte >> 4) as usize]
+    })
+}
@@ -0,0 +1,62 @@
+use std::time::{Duration, SystemTime};
+
+use crate::metrics::MeanMaxPower;
+
+pub(crate) mod csv;
+pub(crate) mod fit;
+pub(crate) mod tcx;
+
+#[derive(Clone, Debug)]
+pub(crate) struct SessionRecord {
+    pub timestamp: SystemTime,
+    pub elapsed: f64,
+    pub speed: f32,
+    pub power: u16,
+    pub cadence: Option<f32>,
+    pub heart_rate: Option<u8>,
+    pub distance: f64,
+    /// Speed, cadence or power was carried forward from an earlier sample.
+    pub held: bool,
+}
+
+pub(crate) struct Summary {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub total_secs: f64,
+    pub distance: f64,
+    pub calories: u16,
+    pub avg_power: u16,
+    pub max_power: u16,
+    /// Best mean power over each of `metrics::PEAK_DURATIONS`.
+    pub peak_powers: [Option<u16>; 4],
+}
+
+impl Summary {
+    pub fn from_records(records: &[SessionRecord]) -> Option<Self> {
+        let first = records.first()?;
+        let last = records.last()?;
+
+        // Approximate kcal from mechanical work, kJ ≈ kcal at ~25 % gross efficiency.
+        let joules: f64 = records
+            .windows(2)
+            .map(|w| w[1].power as f64 * (w[1].elapsed - w[0].elapsed).max(0.0))
+            .sum();
+
+        let mut peaks = MeanMaxPower::default();
+        for record in records {
+            peaks.push(Duration::from_secs_f64(record.elapsed.max(0.0)), record.power);
+        }
+
+        Some(Self {
+            start: first.timestamp,
+            end: last.timestamp,
+            total_secs: (last.elapsed - first.elapsed).max(0.0),
+            distance: last.distance,
+            calories: (joules / 1000.0).round() as u16,
+            avg_power: (records.iter().map(|r| r.power as u64).sum::<u64>() / records.len() as u64)
+                as u16,
+            max_power: records.iter().map(|r| r.power).max().unwrap_or(0),
+            peak_powers: peaks.bests(),
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fs, io, path::Path, time::SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use quick_xml::{
+    events::{BytesDecl, BytesText, Event},
+    Error, Writer,
+};
+
+use super::{SessionRecord, Summary};
+use crate::trainer::DeviceInfo;
+
+const TCX_NAMESPACE: &str = "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2";
+const ACTIVITY_EXTENSION_NAMESPACE: &str = "http://www.garmin.com/xmlschemas/ActivityExtension/v2";
+
+pub(crate) fn write(
+    path: &Path,
+    records: &[SessionRecord],
+    devices: &[DeviceInfo],
+) -> io::Result<()> {
+    let summary = Summary::from_records(records)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no records to export"))?;
+
+    let document = encode(records, &summary, devices)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::write(path, document)
+}
+
+fn encode(
+    records: &[SessionRecord],
+    summary: &Summary,
+    devices: &[DeviceInfo],
+) -> Result<Vec<u8>, Error> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    let start = timestamp(summary.start);
+
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("TrainingCenterDatabase")
+        .with_attribute(("xmlns", TCX_NAMESPACE))
+        .with_attribute(("xmlns:ns3", ACTIVITY_EXTENSION_NAMESPACE))
+        .write_inner_content(|w| -> Result<(), Error> {
+            w.create_element("Activities")
+                .write_inner_content(|w| -> Result<(), Error> {
+                    w.create_element("Activity")
+                        .with_attribute(("Sport", "Biking"))
+                        .write_inner_content(|w| -> Result<(), Error> {
+                            text(w, "Id", &start)?;
+                            w.create_element("Lap")
+                                .with_attribute(("StartTime", start.as_str()))
+                                .write_inner_content(|w| -> Result<(), Error> {
+                                    text(
+                                        w,
+                                        "TotalTimeSeconds",
+                                        &format!("{:.1}", summary.total_secs),
+                                    )?;
+                                    text(w, "DistanceMeters", &format!("{:.1}", summary.distance))?;
+                                    text(w, "Calories", &summary.calories.to_string())?;
+                                    text(w, "Intensity", "Active")?;
+                                    text(w, "TriggerMethod", "Manual")?;
+                                    w.create_element("Track").write_inner_content(
+                                        |w| -> Result<(), Error> {
+                                            records.iter().try_for_each(|r| trackpoint(w, r))
+                                        },
+                                    )?;
+                                    Ok(())
+                                })?;
+                            // The Creator element only fits one device, notes take them all.
+                            if !devices.is_empty() {
+                                let notes: Vec<_> =
+                                    devices.iter().map(|device| device.to_string()).collect();
+                                text(w, "Notes", &notes.join("\n"))?;
+                            }
+                            Ok(())
+                        })?;
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+
+    Ok(writer.into_inner())
+}
+
+fn trackpoint(w: &mut Writer<Vec<u8>>, record: &SessionRecord) -> Result<(), Error> {
+    w.create_element("Trackpoint")
+        .write_inner_content(|w| -> Result<(), Error> {
+            text(w, "Time", &timestamp(record.timestamp))?;
+            text(w, "DistanceMeters", &format!("{:.1}", record.distance))?;
+            if let Some(bpm) = record.heart_rate {
+                w.create_element("HeartRateBpm")
+                    .write_inner_content(|w| text(w, "Value", &bpm.to_string()))?;
+            }
+            if let Some(rpm) = record.cadence {
+                text(w, "Cadence", &format!("{:.0}", rpm.min(254.0)))?;
+            }
+            w.create_element("Extensions")
+                .write_inner_content(|w| -> Result<(), Error> {
+                    w.create_element("ns3:TPX")
+                        .write_inner_content(|w| -> Result<(), Error> {
+                            let speed_ms = record.speed as f64 / 3.6;
+                            text(w, "ns3:Speed", &format!("{:.2}", speed_ms))?;
+                            text(w, "ns3:Watts", &record.power.to_string())
+                        })?;
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn text(w: &mut Writer<Vec<u8>>, name: &str, value: &str) -> Result<(), Error> {
+    w.create_element(name)
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Eases the simulated grade toward its target at up to `max_rate` % per second, so the trainer
+/// doesn't slam on resistance at the foot of a climb. Going downhill applies straight away,
+/// freewheeling shouldn't lag behind the course.
+pub(crate) struct GradeSmoother {
+    pub max_rate: f32,
+    applied: Option<f32>,
+}
+
+impl GradeSmoother {
+    pub fn new(max_rate: f32) -> Self {
+        Self {
+            max_rate,
+            applied: None,
+        }
+    }
+
+    /// Takes `grade` as applied, without easing.
+    pub fn set(&mut self, grade: f32) {
+        self.applied = Some(grade);
+    }
+
+    /// Grade to apply `dt` after the previous tick on the way to `target`.
+    pub fn tick(&mut self, dt: Duration, target: f32) -> f32 {
+        let applied = match self.applied {
+            Some(applied) if target > applied => {
+                (applied + self.max_rate.max(0.0) * dt.as_secs_f32()).min(target)
+            }
+            _ => target,
+        };
+        self.applied = Some(applied);
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_up_at_most_max_rate_per_second() {
+        let mut smoother = GradeSmoother::new(1.0);
+        smoother.set(0.0);
+        assert_eq!(smoother.tick(Duration::from_secs(1), 8.0), 1.0);
+        assert_eq!(smoother.tick(Duration::from_millis(500), 8.0), 1.5);
+        assert_eq!(smoother.tick(Duration::from_secs(2), 8.0), 3.5);
+        // Never overshoots the target.
+        assert_eq!(smoother.tick(Duration::from_secs(10), 8.0), 8.0);
+    }
+
+    #[test]
+    fn applies_descents_at_once() {
+        let mut smoother = GradeSmoother::new(1.0);
+        smoother.set(8.0);
+        assert_eq!(smoother.tick(Duration::from_secs(1), 2.0), 2.0);
+        assert_eq!(smoother.tick(Duration::ZERO, -5.0), -5.0);
+    }
+
+    #[test]
+    fn first_grade_applies_at_once() {
+        let mut smoother = GradeSmoother::new(1.0);
+        assert_eq!(smoother.tick(Duration::ZERO, 8.0), 8.0);
+    }
+}
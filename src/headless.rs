@@ -0,0 +1,150 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use eframe::egui;
+use futures_lite::StreamExt;
+use serde::Serialize;
+use tokio::{runtime::Runtime, sync::mpsc, time};
+use tracing::{info, warn};
+
+use crate::{
+    settings::Settings,
+    trainer::{device_address, ReconnectPolicy, ScanOptions, TrainerUpdate, BT},
+};
+
+const LIST_SCAN_TIME: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct Row {
+    elapsed_seconds: f64,
+    speed_kph: Option<f32>,
+    power_watts: Option<u16>,
+    cadence_rpm: Option<f32>,
+    heart_rate_bpm: Option<u8>,
+    distance_m: Option<u32>,
+}
+
+/// Records a ride without the UI: connects to the named trainer and streams its data to a CSV.
+pub(crate) fn run(device: &str, output: &Path) -> Result<(), Box<dyn Error>> {
+    let rt = Runtime::new()?;
+    rt.block_on(record(device, output))
+}
+
+/// Scans for fitness machines for a few seconds and prints what was found.
+pub(crate) fn list_devices() -> Result<(), Box<dyn Error>> {
+    let rt = Runtime::new()?;
+    rt.block_on(async {
+        let mut bt = BT::init().await?;
+        let mut devices = bt.discover_devices(ScanOptions::default()).await?;
+        // Devices advertise repeatedly, keep the latest RSSI for each address.
+        let mut found = BTreeMap::new();
+        let scan = time::sleep(LIST_SCAN_TIME);
+        tokio::pin!(scan);
+
+        loop {
+            tokio::select! {
+                Some(device) = devices.next() => {
+                    let name = device.device.name().unwrap_or_else(|_| "Unknown".to_string());
+                    found.insert(device_address(&device), (name, device.rssi));
+                }
+                _ = &mut scan => break,
+            }
+        }
+
+        if found.is_empty() {
+            println!("No fitness machines found");
+            return Ok(());
+        }
+        println!("{:<32} {:<40} {:>5}", "NAME", "ADDRESS", "RSSI");
+        for (address, (name, rssi)) in found {
+            let rssi = rssi.map_or("-".to_string(), |rssi| rssi.to_string());
+            println!("{:<32} {:<40} {:>5}", name, address, rssi);
+        }
+        Ok(())
+    })
+}
+
+async fn record(name: &str, output: &Path) -> Result<(), Box<dyn Error>> {
+    let settings = Settings::load();
+    let mut bt = BT::init().await?;
+
+    info!("Scanning for {}", name);
+    let options = ScanOptions {
+        name_prefix: Some(name.to_string()),
+        ..Default::default()
+    };
+    let mut devices = bt.discover_devices(options).await?;
+    let device = loop {
+        match devices.next().await {
+            Some(device) if device.device.name().map_or(false, |n| n == name) => break device,
+            Some(_) => {}
+            None => return Err(format!("Trainer {} not found", name).into()),
+        }
+    };
+    drop(devices);
+
+    let (tx, mut rx) = mpsc::channel(64);
+    let reconnect = ReconnectPolicy {
+        attempts: settings.reconnect_attempts,
+        initial_backoff: Duration::from_secs(settings.reconnect_backoff_secs),
+    };
+    // Nothing is drawn, the context only receives repaint requests.
+    bt.connect(
+        device,
+        tx,
+        egui::Context::default(),
+        None,
+        reconnect,
+        settings.system_weight_kg,
+    )
+    .await?;
+    info!("Connected to {}, recording to {}", name, output.display());
+
+    let mut writer = ::csv::Writer::from_path(output)?;
+    let started = Instant::now();
+    let mut heartbeat = time::interval(Duration::from_secs(1));
+    let mut last_power = None;
+    let mut last_cadence = None;
+    let mut samples = 0u64;
+
+    loop {
+        tokio::select! {
+            update = rx.recv() => match update {
+                Some(TrainerUpdate::Sample(sample)) => {
+                    writer.serialize(Row {
+                        elapsed_seconds: started.elapsed().as_secs_f64(),
+                        speed_kph: sample.speed,
+                        power_watts: sample.power,
+                        cadence_rpm: sample.cadence,
+                        heart_rate_bpm: sample.heart_rate,
+                        distance_m: sample.distance,
+                    })?;
+                    writer.flush()?;
+                    last_power = sample.power.or(last_power);
+                    last_cadence = sample.cadence.or(last_cadence);
+                    samples += 1;
+                }
+                Some(TrainerUpdate::Disconnected) => warn!("Trainer disconnected"),
+                Some(TrainerUpdate::Reconnected) => info!("Trainer reconnected"),
+                Some(TrainerUpdate::ReconnectFailed) | None => break,
+                Some(_) => {}
+            },
+            _ = heartbeat.tick() => {
+                println!(
+                    "{:>6}s  {:>4} W  {:>3} rpm  {} samples",
+                    started.elapsed().as_secs(),
+                    last_power.map_or("-".to_string(), |p| p.to_string()),
+                    last_cadence.map_or("-".to_string(), |c| format!("{:.0}", c)),
+                    samples,
+                );
+            }
+        }
+    }
+
+    writer.flush()?;
+    Err(format!("Lost connection to {}", name).into())
+}
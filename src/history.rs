@@ -0,0 +1,619 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate};
+use eframe::{
+    egui::{self, Ui},
+    epaint::Color32,
+};
+use egui_plot::{Bar, BarChart, Legend, Line, LineStyle, Plot, PlotPoints, Points};
+
+use crate::{
+    metrics::{self, NpCalculator},
+    pmcmetrics,
+    session::Session,
+    trainer::TrainerUpdate,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Column {
+    Date,
+    Duration,
+    Energy,
+    NormalizedPower,
+    Tss,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tab {
+    Sessions,
+    Statistics,
+    Fitness,
+    PowerCurve,
+}
+
+// Extension of the power curve saved next to an archived session.
+const POWER_CURVE_EXTENSION: &str = "mmp";
+const FITNESS_DAYS: usize = 90;
+const CALENDAR_CELL: f32 = 12.0;
+const CALENDAR_GAP: f32 = 2.0;
+// Upper TSS bound of each heat map shade, anything above the last is the darkest.
+const TSS_LEVELS: [(f32, Color32); 4] = [
+    (50.0, Color32::from_rgb(155, 233, 168)),
+    (100.0, Color32::from_rgb(64, 196, 99)),
+    (150.0, Color32::from_rgb(48, 161, 78)),
+    (f32::INFINITY, Color32::from_rgb(33, 110, 57)),
+];
+
+const COLUMNS: [(Column, &str); 5] = [
+    (Column::Date, "Date"),
+    (Column::Duration, "Duration"),
+    (Column::Energy, "kJ"),
+    (Column::NormalizedPower, "NP"),
+    (Column::Tss, "TSS"),
+];
+
+/// A saved session, summarized once when the history is scanned.
+pub(crate) struct SessionSummary {
+    pub path: PathBuf,
+    pub started: DateTime<Local>,
+    pub duration: Duration,
+    pub kilojoules: f64,
+    pub normalized_power: u16,
+    pub tss: f32,
+    /// Mean maximal power, `(seconds, watts)` from 5 s to the session length.
+    pub power_curve: Vec<(u32, f32)>,
+    power: Vec<[f64; 2]>,
+    speed: Vec<[f64; 2]>,
+    cadence: Vec<[f64; 2]>,
+}
+
+impl SessionSummary {
+    pub fn load(path: &Path, ftp: u16) -> bincode::Result<Self> {
+        let modified = fs::metadata(path)?.modified()?;
+        let events = Session::load(path)?.into_events();
+
+        let base = Instant::now();
+        let mut np = NpCalculator::default();
+        let mut kilojoules = 0.0;
+        let mut last_power: Option<(Duration, u16)> = None;
+        let mut power = Vec::new();
+        let mut speed = Vec::new();
+        let mut cadence = Vec::new();
+
+        for (offset, update) in &events {
+            let TrainerUpdate::Sample(sample) = update else {
+                continue;
+            };
+            let secs = offset.as_secs_f64();
+            if let Some(watts) = sample.power {
+                if let Some((at, previous)) = last_power {
+                    let secs = offset.saturating_sub(at).as_secs_f64();
+                    kilojoules += previous as f64 * secs / 1000.0;
+                }
+                last_power = Some((*offset, watts));
+                np.push(base + *offset, watts);
+                power.push([secs, watts as f64]);
+            }
+            if let Some(kph) = sample.speed {
+                speed.push([secs, kph as f64]);
+            }
+            if let Some(rpm) = sample.cadence {
+                cadence.push([secs, rpm as f64]);
+            }
+        }
+
+        let duration = events.last().map_or(Duration::ZERO, |(offset, _)| *offset);
+        // Sessions are saved when they end, so the file time marks the end of the ride.
+        let started = modified.checked_sub(duration).unwrap_or(modified);
+        let normalized_power = np.normalized_power();
+        // Sessions archived before the curve was saved alongside them get it computed here.
+        let power_curve = load_power_curve(path).unwrap_or_else(|| power_curve(&events));
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            started: DateTime::<Local>::from(started),
+            duration,
+            kilojoules,
+            normalized_power,
+            tss: metrics::tss(duration, normalized_power, ftp),
+            power_curve,
+            power,
+            speed,
+            cadence,
+        })
+    }
+}
+
+/// Training load summed over the sessions of a period.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Totals {
+    pub tss: f32,
+    pub kilojoules: f64,
+    pub hours: f64,
+}
+
+impl Totals {
+    /// Totals over the `days` days ending with `today`.
+    pub fn rolling(sessions: &[SessionSummary], today: NaiveDate, days: u64) -> Self {
+        let Some(first) = today.checked_sub_days(Days::new(days.saturating_sub(1))) else {
+            return Self::default();
+        };
+
+        sessions
+            .iter()
+            .filter(|session| (first..=today).contains(&session.started.date_naive()))
+            .fold(Self::default(), |totals, session| Self {
+                tss: totals.tss + session.tss,
+                kilojoules: totals.kilojoules + session.kilojoules,
+                hours: totals.hours + session.duration.as_secs_f64() / 3600.0,
+            })
+    }
+}
+
+/// TSS ridden on each day, for the calendar.
+pub(crate) fn daily_tss(sessions: &[SessionSummary]) -> HashMap<NaiveDate, f32> {
+    let mut days = HashMap::new();
+    for session in sessions {
+        *days.entry(session.started.date_naive()).or_insert(0.0) += session.tss;
+    }
+    days
+}
+
+/// Summarizes every `.session` file in the directory, skipping the ones that fail to load.
+pub(crate) fn scan(dir: &Path, ftp: u16) -> io::Result<Vec<SessionSummary>> {
+    let mut sessions = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "session") {
+            continue;
+        }
+        match SessionSummary::load(&path, ftp) {
+            Ok(summary) => sessions.push(summary),
+            Err(e) => tracing::warn!("Skipping session {}: {}", path.display(), e),
+        }
+    }
+    Ok(sessions)
+}
+
+/// Past sessions as a sortable table, with the selected one charted below it.
+pub(crate) struct HistoryScreen {
+    sessions: Vec<SessionSummary>,
+    tab: Tab,
+    calendar_year: i32,
+    sort: Column,
+    ascending: bool,
+    selected: Option<PathBuf>,
+    // Sessions whose power curves are overlaid.
+    compared: HashSet<PathBuf>,
+}
+
+impl HistoryScreen {
+    pub fn new(sessions: Vec<SessionSummary>) -> Self {
+        let mut screen = Self {
+            sessions,
+            tab: Tab::Sessions,
+            calendar_year: Local::now().year(),
+            sort: Column::Date,
+            ascending: false,
+            selected: None,
+            compared: HashSet::new(),
+        };
+        screen.sort_sessions();
+        screen
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) {
+        if self.sessions.is_empty() {
+            ui.label("No saved sessions yet, they're added here when a session ends.");
+            return;
+        }
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tab, Tab::Sessions, "Sessions");
+            ui.selectable_value(&mut self.tab, Tab::Statistics, "Statistics");
+            ui.selectable_value(&mut self.tab, Tab::Fitness, "Fitness");
+            ui.selectable_value(&mut self.tab, Tab::PowerCurve, "Power curve");
+        });
+        ui.separator();
+
+        match self.tab {
+            Tab::Sessions => self.render_sessions(ui),
+            Tab::Statistics => self.render_statistics(ui),
+            Tab::Fitness => self.render_fitness(ui),
+            Tab::PowerCurve => self.render_power_curves(ui),
+        }
+    }
+
+    fn render_sessions(&mut self, ui: &mut Ui) {
+        egui::ScrollArea::vertical()
+            .max_height(ui.available_height() / 2.0)
+            .show(ui, |ui| {
+                egui::Grid::new("history")
+                    .num_columns(COLUMNS.len())
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (column, title) in COLUMNS {
+                            let title = match (self.sort == column, self.ascending) {
+                                (true, true) => format!("{} ⏶", title),
+                                (true, false) => format!("{} ⏷", title),
+                                (false, _) => title.to_string(),
+                            };
+                            if ui.button(title).clicked() {
+                                self.sort_by(column);
+                            }
+                        }
+                        ui.end_row();
+
+                        for session in &self.sessions {
+                            let selected = self.selected.as_ref() == Some(&session.path);
+                            let date = session.started.format("%Y-%m-%d %H:%M").to_string();
+                            if ui.selectable_label(selected, date).clicked() {
+                                self.selected = Some(session.path.clone());
+                            }
+                            ui.label(format_duration(session.duration));
+                            ui.label(format!("{:.0}", session.kilojoules));
+                            ui.label(format!("{} W", session.normalized_power));
+                            ui.label(format!("{:.0}", session.tss));
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        let selected = self
+            .sessions
+            .iter()
+            .find(|session| self.selected.as_ref() == Some(&session.path));
+        if let Some(session) = selected {
+            ui.separator();
+            render_chart(ui, session);
+        }
+    }
+
+    fn render_statistics(&mut self, ui: &mut Ui) {
+        let today = Local::now().date_naive();
+        let week = Totals::rolling(&self.sessions, today, 7);
+        let month = Totals::rolling(&self.sessions, today, 28);
+
+        // Each measure gets its own chart, their scales are too far apart to share an axis.
+        let groups = [
+            ("TSS", week.tss as f64, month.tss as f64),
+            ("kJ", week.kilojoules, month.kilojoules),
+            ("Hours", week.hours, month.hours),
+        ];
+        for (name, week, month) in groups {
+            ui.label(name);
+            Plot::new(("totals", name))
+                .legend(Legend::default())
+                .height(60.0)
+                .show_y(false)
+                .show_axes([true, false])
+                .allow_drag(false)
+                .allow_zoom(false)
+                .allow_scroll(false)
+                .include_x(0.0)
+                .show(ui, |plot_ui| {
+                    plot_ui.bar_chart(
+                        BarChart::new(vec![Bar::new(1.0, week)])
+                            .horizontal()
+                            .name("Last 7 days"),
+                    );
+                    plot_ui.bar_chart(
+                        BarChart::new(vec![Bar::new(0.0, month)])
+                            .horizontal()
+                            .name("Last 28 days"),
+                    );
+                });
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("⏴").clicked() {
+                self.calendar_year -= 1;
+            }
+            ui.label(self.calendar_year.to_string());
+            if ui.button("⏵").clicked() {
+                self.calendar_year += 1;
+            }
+        });
+        render_calendar(ui, &daily_tss(&self.sessions), self.calendar_year);
+    }
+
+    fn render_fitness(&self, ui: &mut Ui) {
+        let today = Local::now().date_naive();
+        let daily = daily_tss(&self.sessions);
+        let Some(&first) = daily.keys().min() else {
+            return;
+        };
+        // Fitness builds up from the very first session, only the end of it is shown.
+        let tss: Vec<f32> = first
+            .iter_days()
+            .take_while(|date| *date <= today)
+            .map(|date| daily.get(&date).copied().unwrap_or(0.0))
+            .collect();
+        let loads = pmcmetrics::loads(&tss);
+        let shown = &loads[loads.len().saturating_sub(FITNESS_DAYS)..];
+        if shown.is_empty() {
+            return;
+        }
+
+        // Days are counted back from today, which is 0.
+        let days_ago = |i: usize| i as f64 - (shown.len() - 1) as f64;
+        let series = |value: fn(&pmcmetrics::Load) -> f32| -> PlotPoints {
+            shown
+                .iter()
+                .enumerate()
+                .map(|(i, load)| [days_ago(i), value(load) as f64])
+                .collect()
+        };
+        let marked = |keep: fn(&pmcmetrics::Load) -> bool| -> PlotPoints {
+            shown
+                .iter()
+                .enumerate()
+                .filter(|(_, load)| keep(load))
+                .map(|(i, load)| [days_ago(i), load.tsb as f64])
+                .collect()
+        };
+
+        Plot::new("Fitness")
+            .legend(Legend::default())
+            .clamp_grid(true)
+            .x_axis_label("Days from today")
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(series(|load| load.ctl)).name("Fitness (CTL)"));
+                plot_ui.line(Line::new(series(|load| load.atl)).name("Fatigue (ATL)"));
+                plot_ui.line(
+                    Line::new(series(|load| load.tsb))
+                        .style(LineStyle::dashed_loose())
+                        .name("Form (TSB)"),
+                );
+                plot_ui.points(
+                    Points::new(marked(pmcmetrics::Load::is_overreached))
+                        .radius(4.0)
+                        .color(Color32::RED)
+                        .name("Overreached"),
+                );
+                plot_ui.points(
+                    Points::new(marked(pmcmetrics::Load::is_fresh))
+                        .radius(4.0)
+                        .color(Color32::GREEN)
+                        .name("Fresh"),
+                );
+            });
+    }
+
+    fn render_power_curves(&mut self, ui: &mut Ui) {
+        // Start from the session picked in the list.
+        if self.compared.is_empty() {
+            self.compared.extend(self.selected.clone());
+        }
+
+        egui::SidePanel::left("power_curve_sessions")
+            .resizable(false)
+            .show_inside(ui, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for session in &self.sessions {
+                        let mut compared = self.compared.contains(&session.path);
+                        let date = session.started.format("%Y-%m-%d %H:%M").to_string();
+                        if ui.checkbox(&mut compared, date).changed() {
+                            if compared {
+                                self.compared.insert(session.path.clone());
+                            } else {
+                                self.compared.remove(&session.path);
+                            }
+                        }
+                    }
+                });
+            });
+
+        // Durations go on a log scale, the first minute would be a sliver otherwise.
+        Plot::new("Power curve")
+            .legend(Legend::default())
+            .clamp_grid(true)
+            .x_axis_label("Duration")
+            .x_axis_formatter(|value, _, _| {
+                metrics::peak_label(Duration::from_secs_f64(10f64.powf(value).clamp(0.0, 1e9)))
+            })
+            .y_axis_label("Power (W)")
+            .show(ui, |plot_ui| {
+                for session in &self.sessions {
+                    if !self.compared.contains(&session.path) {
+                        continue;
+                    }
+                    let points: PlotPoints = session
+                        .power_curve
+                        .iter()
+                        .map(|&(secs, watts)| [(secs as f64).log10(), watts as f64])
+                        .collect();
+                    let name = session.started.format("%Y-%m-%d %H:%M").to_string();
+                    plot_ui.line(Line::new(points).name(name));
+                }
+            });
+    }
+
+    fn sort_by(&mut self, column: Column) {
+        if self.sort == column {
+            self.ascending = !self.ascending;
+        } else {
+            self.sort = column;
+            self.ascending = false;
+        }
+        self.sort_sessions();
+    }
+
+    fn sort_sessions(&mut self) {
+        let (column, ascending) = (self.sort, self.ascending);
+        self.sessions.sort_by(|a, b| {
+            let ordering = match column {
+                Column::Date => a.started.cmp(&b.started),
+                Column::Duration => a.duration.cmp(&b.duration),
+                Column::Energy => a.kilojoules.total_cmp(&b.kilojoules),
+                Column::NormalizedPower => a.normalized_power.cmp(&b.normalized_power),
+                Column::Tss => a.tss.total_cmp(&b.tss),
+            };
+            if ascending {
+                ordering
+            } else {
+                ordering.reverse()
+            }
+        });
+    }
+}
+
+fn render_chart(ui: &mut Ui, session: &SessionSummary) {
+    let points = |series: &[[f64; 2]]| PlotPoints::from(series.to_vec());
+
+    Plot::new("Session")
+        .legend(Legend::default())
+        .clamp_grid(true)
+        .x_axis_label("Elapsed (s)")
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(points(&session.power)).fill(0.0).name("Power (W)"));
+            plot_ui.line(
+                Line::new(points(&session.speed))
+                    .style(LineStyle::dashed_loose())
+                    .name("Speed (km/h)"),
+            );
+            plot_ui.line(Line::new(points(&session.cadence)).name("Cadence (rpm)"));
+        });
+}
+
+/// One cell per day of the year, a column per week from Monday down, shaded by TSS.
+fn render_calendar(ui: &mut Ui, daily: &HashMap<NaiveDate, f32>, year: i32) {
+    let Some(first) = NaiveDate::from_ymd_opt(year, 1, 1) else {
+        return;
+    };
+    let step = CALENDAR_CELL + CALENDAR_GAP;
+    let (rect, response) =
+        ui.allocate_exact_size(egui::vec2(54.0 * step, 7.0 * step), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let empty = ui.visuals().faint_bg_color;
+    let lead = first.weekday().num_days_from_monday();
+    let hovered = response.hover_pos();
+    let mut tooltip = None;
+
+    for date in first.iter_days().take_while(|date| date.year() == year) {
+        let index = date.ordinal0() + lead;
+        let min = rect.min + egui::vec2((index / 7) as f32 * step, (index % 7) as f32 * step);
+        let cell = egui::Rect::from_min_size(min, egui::vec2(CALENDAR_CELL, CALENDAR_CELL));
+        let tss = daily.get(&date).copied().unwrap_or(0.0);
+        let color = if tss > 0.0 {
+            TSS_LEVELS
+                .iter()
+                .find(|(limit, _)| tss <= *limit)
+                .map_or(empty, |(_, color)| *color)
+        } else {
+            empty
+        };
+        painter.rect_filled(cell, 2.0, color);
+
+        if hovered.map_or(false, |pos| cell.contains(pos)) {
+            tooltip = Some(format!("{}: {:.0} TSS", date.format("%a %-d %b"), tss));
+        }
+    }
+
+    if let Some(text) = tooltip {
+        response.on_hover_text_at_pointer(text);
+    }
+}
+
+/// Mean maximal power curve of a recorded session.
+pub(crate) fn power_curve(events: &[(Duration, TrainerUpdate)]) -> Vec<(u32, f32)> {
+    let power: Vec<[f64; 2]> = events
+        .iter()
+        .filter_map(|(offset, update)| match update {
+            TrainerUpdate::Sample(sample) => sample
+                .power
+                .map(|watts| [offset.as_secs_f64(), watts as f64]),
+            _ => None,
+        })
+        .collect();
+    let duration = events.last().map_or(Duration::ZERO, |(offset, _)| *offset);
+    metrics::mean_max_curve(&per_second(&power, duration))
+}
+
+/// Saves `curve` next to the session at `session`, so the history doesn't recompute it.
+pub(crate) fn save_power_curve(session: &Path, curve: &[(u32, f32)]) -> bincode::Result<()> {
+    let writer = BufWriter::new(File::create(session.with_extension(POWER_CURVE_EXTENSION))?);
+    bincode::serialize_into(writer, curve)
+}
+
+/// The power curve saved next to `session`, `None` when there is none to use.
+fn load_power_curve(session: &Path) -> Option<Vec<(u32, f32)>> {
+    let path = session.with_extension(POWER_CURVE_EXTENSION);
+    let reader = BufReader::new(File::open(&path).ok()?);
+    bincode::deserialize_from(reader)
+        .map_err(|e| tracing::warn!("Ignoring power curve {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Power held through each whole second of the session, from the last sample before it.
+fn per_second(power: &[[f64; 2]], duration: Duration) -> Vec<u16> {
+    let mut samples = power.iter().peekable();
+    let mut watts = 0;
+    (0..duration.as_secs())
+        .map(|sec| {
+            while let Some(sample) = samples.next_if(|sample| sample[0] <= sec as f64) {
+                watts = sample[1] as u16;
+            }
+            watts
+        })
+        .collect()
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// File name for a session ending now, unique to the second.
+pub(crate) fn session_file_name() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("ride-{}.session", now.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ftms::BikeSample;
+
+    use super::*;
+
+    #[test]
+    fn power_curve_is_read_from_the_sidecar() {
+        let dir = std::env::temp_dir().join(format!("bike-trainer-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ride-1.session");
+
+        let mut session = Session::default();
+        for secs in 0..=10 {
+            let sample = BikeSample {
+                power: Some(200),
+                ..Default::default()
+            };
+            session.push(Duration::from_secs(secs), TrainerUpdate::Sample(sample));
+        }
+        session.save(&path).unwrap();
+
+        // Without a sidecar, as for sessions archived before it existed.
+        let computed = power_curve(session.events());
+        assert!(computed.iter().all(|&(_, watts)| watts == 200.0));
+        assert_eq!(computed.last(), Some(&(10, 200.0)));
+        assert_eq!(
+            SessionSummary::load(&path, 250).unwrap().power_curve,
+            computed
+        );
+
+        let saved = [(5, 300.0), (10, 250.0)];
+        save_power_curve(&path, &saved).unwrap();
+        assert_eq!(SessionSummary::load(&path, 250).unwrap().power_curve, saved);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,62 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
 
+use clap::Parser;
 use tracing_subscriber;
 
 mod app;
+mod dashboard;
+mod erg;
+mod export;
+mod ftms;
+mod grade;
+mod headless;
+#[cfg(feature = "serde")]
+mod history;
+mod metrics;
+mod pmcmetrics;
+mod session;
+mod settings;
+mod spike;
+mod timer;
 mod trainer;
+mod upload;
+mod workout;
+mod zones;
+
+#[derive(Parser)]
+#[command(about = "Indoor bike trainer control")]
+struct Args {
+    /// Fullscreen, borderless and with large text, for a tablet on the handlebars.
+    #[arg(long)]
+    kiosk: bool,
+    /// Serve the ride data as JSON on http://127.0.0.1:8080.
+    #[arg(long)]
+    web_dashboard: bool,
+    /// Scan for 5 s, print the fitness machines in range and exit.
+    #[arg(long)]
+    list_devices: bool,
+    /// Record the named trainer to a CSV without opening a window.
+    #[arg(long, requires_all = ["device", "output"])]
+    headless: bool,
+    /// Trainer name to connect to in headless mode.
+    #[arg(long)]
+    device: Option<String>,
+    /// CSV file to write in headless mode.
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
     tracing_subscriber::fmt::init();
-    app::run()?;
+    if args.list_devices {
+        return headless::list_devices();
+    }
+    if args.headless {
+        if let (Some(device), Some(output)) = (&args.device, &args.output) {
+            return headless::run(device, output);
+        }
+    }
+    app::run(args.kiosk, args.web_dashboard)?;
     Ok(())
 }
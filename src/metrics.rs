@@ -0,0 +1,309 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+const NP_WINDOW: Duration = Duration::from_secs(30);
+const MMP_SHORTEST_SECS: u32 = 5;
+
+/// Durations `MeanMaxPower` tracks the best average power over.
+pub(crate) const PEAK_DURATIONS: [Duration; 4] = [
+    Duration::from_secs(5),
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(20 * 60),
+];
+
+/// Normalized Power: the fourth root of the mean of the fourth powers of the 30 s rolling
+/// average power.
+#[derive(Default)]
+pub(crate) struct NpCalculator {
+    window: VecDeque<(Instant, u16)>,
+    window_sum: u32,
+    started: Option<Instant>,
+    fourth_power_sum: f64,
+    count: u32,
+}
+
+impl NpCalculator {
+    pub fn push(&mut self, at: Instant, watts: u16) {
+        let started = *self.started.get_or_insert(at);
+
+        self.window.push_back((at, watts));
+        self.window_sum += watts as u32;
+        while let Some(&(oldest, oldest_watts)) = self.window.front() {
+            if at.duration_since(oldest) < NP_WINDOW {
+                break;
+            }
+            self.window.pop_front();
+            self.window_sum -= oldest_watts as u32;
+        }
+
+        // The rolling average only means something once a full window has been collected.
+        if at.duration_since(started) >= NP_WINDOW {
+            let average = self.window_sum as f64 / self.window.len() as f64;
+            self.fourth_power_sum += average.powi(4);
+            self.count += 1;
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.count > 0
+    }
+
+    pub fn normalized_power(&self) -> u16 {
+        if self.count == 0 {
+            return 0;
+        }
+        (self.fourth_power_sum / self.count as f64)
+            .powf(0.25)
+            .round() as u16
+    }
+}
+
+/// Best average power over each of `PEAK_DURATIONS`, from power samples stamped with the time
+/// since the start of the ride.
+#[derive(Default)]
+pub(crate) struct MeanMaxPower {
+    samples: VecDeque<(Duration, u16)>,
+    started: Option<Duration>,
+    // Per duration: index of the oldest sample in its window, the window's sum and the best mean.
+    windows: [(usize, u32, Option<u16>); 4],
+}
+
+impl MeanMaxPower {
+    pub fn push(&mut self, at: Duration, watts: u16) {
+        let started = *self.started.get_or_insert(at);
+        self.samples.push_back((at, watts));
+
+        for (duration, (start, sum, best)) in PEAK_DURATIONS.iter().zip(&mut self.windows) {
+            *sum += watts as u32;
+            while at.saturating_sub(self.samples[*start].0) >= *duration {
+                *sum -= self.samples[*start].1 as u32;
+                *start += 1;
+            }
+
+            // Only a full window counts, a short burst shouldn't pass for a 20 min effort.
+            if at.saturating_sub(started) >= *duration {
+                let mean = (*sum as f64 / (self.samples.len() - *start) as f64).round() as u16;
+                *best = Some(best.map_or(mean, |best| best.max(mean)));
+            }
+        }
+
+        // Drop what even the longest window has moved past.
+        let consumed = self.windows.iter().map(|(start, _, _)| *start).min().unwrap_or(0);
+        self.samples.drain(..consumed);
+        for (start, _, _) in &mut self.windows {
+            *start -= consumed;
+        }
+    }
+
+    /// Best mean power for each of `PEAK_DURATIONS`, `None` until a full window was ridden.
+    pub fn bests(&self) -> [Option<u16>; 4] {
+        self.windows.map(|(_, _, best)| best)
+    }
+}
+
+/// Average of the powers in `buffer` recorded within `window` of `now`, 0 when there are none.
+pub(crate) fn rolling_avg_power(
+    buffer: &VecDeque<(Instant, u16)>,
+    window: Duration,
+    now: Instant,
+) -> u16 {
+    let (sum, count) = buffer
+        .iter()
+        .filter(|(at, _)| now.saturating_duration_since(*at) <= window)
+        .fold((0u32, 0u32), |(sum, count), (_, power)| {
+            (sum + *power as u32, count + 1)
+        });
+
+    if count == 0 {
+        0
+    } else {
+        (sum as f32 / count as f32).round() as u16
+    }
+}
+
+/// Mean maximal power curve of a ride sampled once a second: the best average power held for
+/// each duration from 5 s to the whole ride, as `(seconds, watts)`.
+pub(crate) fn mean_max_curve(power: &[u16]) -> Vec<(u32, f32)> {
+    let mut sums = Vec::with_capacity(power.len() + 1);
+    sums.push(0u64);
+    for &watts in power {
+        sums.push(sums[sums.len() - 1] + watts as u64);
+    }
+
+    curve_durations(power.len() as u32)
+        .into_iter()
+        .map(|secs| {
+            let len = secs as usize;
+            let best = sums.windows(len + 1).map(|w| w[len] - w[0]).max().unwrap_or(0);
+            (secs, best as f32 / secs as f32)
+        })
+        .collect()
+}
+
+/// Every second for the first minute, then about 5 % apart, which is as fine as a chart on a
+/// log scale can show. The full length always closes the curve.
+fn curve_durations(len: u32) -> Vec<u32> {
+    let mut durations = Vec::new();
+    let mut secs = MMP_SHORTEST_SECS;
+    while secs < len {
+        durations.push(secs);
+        secs = if secs < 60 {
+            secs + 1
+        } else {
+            (secs as f32 * 1.05).ceil() as u32
+        };
+    }
+    if len >= MMP_SHORTEST_SECS {
+        durations.push(len);
+    }
+    durations
+}
+
+/// Short label for a peak duration, such as "5 s" or "20 min".
+pub(crate) fn peak_label(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{} s", secs)
+    } else {
+        format!("{} min", secs / 60)
+    }
+}
+
+/// Distance ridden, integrated from speed samples.
+#[derive(Default)]
+pub(crate) struct DistanceAccumulator {
+    meters: f64,
+}
+
+impl DistanceAccumulator {
+    pub fn tick(&mut self, speed_kmh: f32, delta: Duration) {
+        self.meters += speed_kmh as f64 / 3.6 * delta.as_secs_f64();
+    }
+
+    pub fn meters(&self) -> f64 {
+        self.meters
+    }
+}
+
+/// Mechanical work done, integrated from power samples.
+#[derive(Default)]
+pub(crate) struct EnergyAccumulator {
+    joules: f64,
+}
+
+impl EnergyAccumulator {
+    pub fn tick(&mut self, power: u16, delta: Duration) {
+        self.joules += power as f64 * delta.as_secs_f64();
+    }
+
+    pub fn kj(&self) -> f32 {
+        (self.joules / 1000.0) as f32
+    }
+
+    /// Food energy burned. At ~25 % gross efficiency this works out to about one kcal per kJ
+    /// of work, since 1 kcal is 4.184 kJ.
+    pub fn kcal(&self) -> f32 {
+        self.kj()
+    }
+}
+
+/// Exponential moving average of power, `alpha` being the weight of each new sample: 0.0
+/// smooths the most, 1.0 passes raw power through.
+pub(crate) struct PowerFilter {
+    pub alpha: f32,
+    last: Option<f32>,
+}
+
+impl PowerFilter {
+    pub fn new(alpha: f32) -> Self {
+        Self { alpha, last: None }
+    }
+
+    pub fn filter(&mut self, raw: u16) -> u16 {
+        let alpha = self.alpha.clamp(0.0, 1.0);
+        let filtered = match self.last {
+            Some(last) => alpha * raw as f32 + (1.0 - alpha) * last,
+            None => raw as f32,
+        };
+        self.last = Some(filtered);
+        filtered.round() as u16
+    }
+}
+
+/// Power to body weight ratio, `None` while the weight is unknown.
+pub(crate) fn watts_per_kg(watts: u16, weight_kg: f32) -> Option<f32> {
+    (weight_kg > 0.0).then(|| watts as f32 / weight_kg)
+}
+
+pub(crate) fn intensity_factor(np: u16, ftp: u16) -> f32 {
+    np as f32 / ftp.max(1) as f32
+}
+
+/// Training Stress Score, 100 being one hour ridden at FTP.
+pub(crate) fn tss(duration: Duration, np: u16, ftp: u16) -> f32 {
+    if ftp == 0 {
+        return 0.0;
+    }
+    let intensity = intensity_factor(np, ftp);
+    duration.as_secs_f32() * np as f32 * intensity / (ftp as f32 * 3600.0) * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_average_of_empty_buffer_is_zero() {
+        let buffer = VecDeque::new();
+        assert_eq!(
+            rolling_avg_power(&buffer, Duration::from_secs(3), Instant::now()),
+            0
+        );
+    }
+
+    #[test]
+    fn rolling_average_only_counts_samples_in_the_window() {
+        let start = Instant::now();
+        let buffer: VecDeque<_> = [(0, 400), (5, 100), (8, 200), (10, 300)]
+            .into_iter()
+            .map(|(secs, watts)| (start + Duration::from_secs(secs), watts))
+            .collect();
+        let now = start + Duration::from_secs(10);
+
+        assert_eq!(rolling_avg_power(&buffer, Duration::from_secs(3), now), 250);
+        assert_eq!(rolling_avg_power(&buffer, Duration::from_secs(5), now), 200);
+        assert_eq!(
+            rolling_avg_power(&buffer, Duration::from_secs(30), now),
+            250
+        );
+    }
+
+    #[test]
+    fn rolling_average_is_zero_once_the_window_has_passed() {
+        let start = Instant::now();
+        let buffer = VecDeque::from([(start, 250)]);
+        let now = start + Duration::from_secs(31);
+        assert_eq!(rolling_avg_power(&buffer, Duration::from_secs(30), now), 0);
+    }
+
+    #[test]
+    fn tss_of_zero_duration_is_zero() {
+        assert_eq!(tss(Duration::ZERO, 250, 250), 0.0);
+    }
+
+    #[test]
+    fn tss_without_ftp_is_zero() {
+        let score = tss(Duration::from_secs(3600), 250, 0);
+        assert_eq!(score, 0.0);
+        assert!(score.is_finite());
+    }
+
+    #[test]
+    fn tss_of_one_hour_at_ftp_is_100() {
+        let score = tss(Duration::from_secs(3600), 250, 250);
+        assert!((score - 100.0).abs() < 1e-3, "{}", score);
+    }
+}
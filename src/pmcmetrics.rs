@@ -0,0 +1,104 @@
+/// Time constant of Chronic Training Load (fitness), in days.
+pub(crate) const CTL_DAYS: f32 = 42.0;
+/// Time constant of Acute Training Load (fatigue), in days.
+pub(crate) const ATL_DAYS: f32 = 7.0;
+/// Form below this means the rider is overreaching.
+pub(crate) const OVERREACHED_TSB: f32 = -30.0;
+/// Form above this means the rider is fresh.
+pub(crate) const FRESH_TSB: f32 = 25.0;
+
+/// Performance Management Chart values at the end of a day.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Load {
+    pub ctl: f32,
+    pub atl: f32,
+    /// Training Stress Balance (form), CTL minus ATL.
+    pub tsb: f32,
+}
+
+impl Load {
+    /// Carries the load over to the next day, on which `tss` was ridden.
+    pub fn next(self, tss: f32) -> Self {
+        let ctl = ewma(self.ctl, tss, CTL_DAYS);
+        let atl = ewma(self.atl, tss, ATL_DAYS);
+        Self {
+            ctl,
+            atl,
+            tsb: ctl - atl,
+        }
+    }
+
+    pub fn is_overreached(&self) -> bool {
+        self.tsb < OVERREACHED_TSB
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.tsb > FRESH_TSB
+    }
+}
+
+/// One day of an exponentially weighted moving average with the given time constant.
+pub(crate) fn ewma(previous: f32, tss: f32, days: f32) -> f32 {
+    previous + (tss - previous) / days.max(1.0)
+}
+
+/// The load after each day of `daily_tss`, starting from no training at all.
+pub(crate) fn loads(daily_tss: &[f32]) -> Vec<Load> {
+    daily_tss
+        .iter()
+        .scan(Load::default(), |load, &tss| {
+            *load = load.next(tss);
+            Some(*load)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f32, expected: f32) -> bool {
+        (actual - expected).abs() < 0.01
+    }
+
+    #[test]
+    fn ewma_converges_to_a_constant_daily_tss() {
+        let settled = (0..1000).fold(0.0, |ctl, _| ewma(ctl, 80.0, CTL_DAYS));
+        assert!(close(settled, 80.0), "{}", settled);
+    }
+
+    #[test]
+    fn no_days_give_no_loads() {
+        assert!(loads(&[]).is_empty());
+    }
+
+    #[test]
+    fn form_is_fitness_minus_fatigue() {
+        let daily_tss = [120.0, 0.0, 80.0, 250.0, 0.0, 0.0, 60.0];
+        for load in loads(&daily_tss) {
+            assert_eq!(load.tsb, load.ctl - load.atl);
+        }
+    }
+
+    #[test]
+    fn loads_after_a_fixed_daily_tss() {
+        let loads = loads(&[100.0; 42]);
+        // 100 × (1 − (1 − 1/42)^42) and 100 × (1 − (1 − 1/7)^7).
+        assert!(close(loads[41].ctl, 63.654), "{}", loads[41].ctl);
+        assert!(close(loads[6].atl, 66.008), "{}", loads[6].atl);
+    }
+
+    #[test]
+    fn form_thresholds_are_exclusive() {
+        let load = |tsb| Load {
+            ctl: 0.0,
+            atl: 0.0,
+            tsb,
+        };
+        assert!(!load(OVERREACHED_TSB).is_overreached());
+        assert!(load(OVERREACHED_TSB - 0.1).is_overreached());
+        assert!(!load(FRESH_TSB).is_fresh());
+        assert!(load(FRESH_TSB + 0.1).is_fresh());
+        assert!(!load(0.0).is_overreached() && !load(0.0).is_fresh());
+    }
+}
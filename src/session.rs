@@ -0,0 +1,52 @@
+#[cfg(feature = "serde")]
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::trainer::TrainerUpdate;
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(crate) struct Session {
+    events: Vec<(Duration, TrainerUpdate)>,
+}
+
+impl Session {
+    pub fn push(&mut self, offset: Duration, update: TrainerUpdate) {
+        self.events.push((offset, update));
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn events(&self) -> &[(Duration, TrainerUpdate)] {
+        &self.events
+    }
+
+    pub fn into_events(self) -> Vec<(Duration, TrainerUpdate)> {
+        self.events
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load(path: &Path) -> bincode::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        bincode::deserialize_from(reader)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save(&self, path: &Path) -> bincode::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(writer, self)
+    }
+}
@@ -0,0 +1,239 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Units {
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    pub fn format_speed(&self, kmh: f64) -> String {
+        match self {
+            Units::Metric => format!("{:.1} km/h", kmh),
+            Units::Imperial => format!("{:.1} mph", kmh * MILES_PER_KM),
+        }
+    }
+
+    pub fn format_distance(&self, meters: f64) -> String {
+        match self {
+            Units::Metric => format!("{:.2} km", meters / 1000.0),
+            Units::Imperial => format!("{:.2} mi", meters / 1000.0 * MILES_PER_KM),
+        }
+    }
+}
+
+const MILES_PER_KM: f64 = 0.621371;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FavoriteDevice {
+    pub name: String,
+    pub address: String,
+}
+
+/// One point of the cadence to power curve, the target is interpolated between points.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CadencePoint {
+    pub rpm: f32,
+    pub watts: u16,
+}
+
+/// Correction for a trainer that reads off from the rider's own power meter, applied to the
+/// ERG targets sent to it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PowerBias {
+    pub device_id: String,
+    pub watts: i16,
+    pub pct: f32,
+}
+
+impl PowerBias {
+    pub fn is_zero(&self) -> bool {
+        self.watts == 0 && self.pct == 0.0
+    }
+
+    /// The power to command for a nominal target.
+    pub fn apply(&self, watts: u16) -> u16 {
+        let biased = watts as f32 * (1.0 + self.pct / 100.0) + self.watts as f32;
+        biased.round().max(0.0) as u16
+    }
+
+    /// Maps a power reported by the trainer back onto the nominal scale.
+    pub fn remove(&self, watts: u16) -> u16 {
+        let nominal = (watts as f32 - self.watts as f32) / (1.0 + self.pct / 100.0);
+        nominal.round().max(0.0) as u16
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub last_device: Option<String>,
+    pub last_device_name: Option<String>,
+    pub units: Units,
+    pub dark_mode: bool,
+    /// Zoom on top of the OS scale factor.
+    pub ui_scale: f32,
+    pub window_width: f32,
+    pub window_height: f32,
+    pub ftp: u16,
+    /// Rider plus bike, what trainers need to turn simulated grade into resistance.
+    #[serde(alias = "rider_weight_kg")]
+    pub system_weight_kg: f32,
+    /// Body weight for W/kg, 0 until the rider enters it.
+    pub weight_kg: f32,
+    pub history_capacity: usize,
+    pub chart_window: usize,
+    pub hold_secs: u64,
+    pub spike_filter: bool,
+    pub spike_threshold: u16,
+    pub power_smoothing: f32,
+    pub trainer_difficulty_pct: f32,
+    pub grade_smoothing: bool,
+    pub max_grade_rate: f32,
+    pub virtual_gears: u8,
+    pub gear_step_pct: f32,
+    /// ERG targets below this are raised to it so the flywheel keeps spinning, 0 to disable.
+    pub min_erg_power: u16,
+    pub hr_target_bpm: u8,
+    pub hr_min_power: u16,
+    pub hr_max_power: u16,
+    /// For speed from a wheel sensor, 2105 mm is a 700x25c tyre.
+    pub wheel_circumference_mm: u16,
+    pub spiral_guard: bool,
+    pub spiral_cadence_floor: f32,
+    pub spiral_reduction_pct: f32,
+    pub record_raw: bool,
+    pub raw_log_dir: PathBuf,
+    /// Sessions are saved here when they end and listed in the history.
+    pub sessions_dir: PathBuf,
+    pub reconnect_attempts: u32,
+    pub reconnect_backoff_secs: u64,
+    pub simulation_interval_ms: u64,
+    /// Client id of the rider's own Strava API application. The client secret and the tokens
+    /// live in the OS keychain.
+    pub strava_client_id: String,
+    /// When the Strava access token in the keychain expires, in unix seconds.
+    pub strava_expires_at: u64,
+    /// The password lives in the OS keychain.
+    pub garmin_username: String,
+    // Kept last, TOML needs arrays of tables after plain values.
+    pub cadence_curve: Vec<CadencePoint>,
+    pub power_biases: Vec<PowerBias>,
+    pub favorites: Vec<FavoriteDevice>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            last_device: None,
+            last_device_name: None,
+            units: Units::Metric,
+            dark_mode: true,
+            ui_scale: 1.0,
+            window_width: 1024.0,
+            window_height: 768.0,
+            ftp: 200,
+            system_weight_kg: 75.0,
+            weight_kg: 0.0,
+            history_capacity: 3600,
+            chart_window: 300,
+            hold_secs: 3,
+            spike_filter: false,
+            spike_threshold: 400,
+            power_smoothing: 0.7,
+            trainer_difficulty_pct: 100.0,
+            grade_smoothing: false,
+            max_grade_rate: 1.0,
+            virtual_gears: 11,
+            gear_step_pct: 0.5,
+            min_erg_power: 0,
+            hr_target_bpm: 145,
+            hr_min_power: 80,
+            hr_max_power: 300,
+            wheel_circumference_mm: 2105,
+            spiral_guard: false,
+            spiral_cadence_floor: 55.0,
+            spiral_reduction_pct: 30.0,
+            record_raw: false,
+            raw_log_dir: dirs::home_dir()
+                .map(|home| home.join(".local/share/bike-trainer/raw"))
+                .unwrap_or_default(),
+            sessions_dir: dirs::home_dir()
+                .map(|home| home.join(".local/share/bike-trainer/sessions"))
+                .unwrap_or_default(),
+            reconnect_attempts: 5,
+            reconnect_backoff_secs: 1,
+            simulation_interval_ms: 500,
+            strava_client_id: String::new(),
+            strava_expires_at: 0,
+            garmin_username: String::new(),
+            cadence_curve: vec![
+                CadencePoint { rpm: 60.0, watts: 100 },
+                CadencePoint { rpm: 90.0, watts: 200 },
+                CadencePoint { rpm: 110.0, watts: 280 },
+            ],
+            power_biases: Vec::new(),
+            favorites: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let Some(path) = settings_path() else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                tracing::error!("Invalid settings file {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                tracing::error!("Failed to read settings file {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn power_bias(&self, device_id: &str) -> Option<&PowerBias> {
+        self.power_biases
+            .iter()
+            .find(|bias| bias.device_id == device_id)
+    }
+
+    pub fn power_bias_mut(&mut self, device_id: &str) -> &mut PowerBias {
+        let index = match self.power_biases.iter().position(|bias| bias.device_id == device_id) {
+            Some(index) => index,
+            None => {
+                self.power_biases.push(PowerBias {
+                    device_id: device_id.to_string(),
+                    ..Default::default()
+                });
+                self.power_biases.len() - 1
+            }
+        };
+        &mut self.power_biases[index]
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = settings_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "home directory not found"))?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents = toml::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, contents)
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config/bike-trainer/settings.toml"))
+}
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use tracing::warn;
+
+use crate::ftms::BikeSample;
+
+const MEDIAN_SAMPLES: usize = 5;
+
+/// Drops single-sample power spikes. A sample whose power jumps more than the threshold above the
+/// recent median is held back until the next one arrives: if that confirms the new level (a real
+/// sprint) both pass through, otherwise the spike's power is discarded. Samples without power that
+/// arrive in the meantime are held behind it, so the order never changes.
+#[derive(Default)]
+pub(crate) struct SpikeFilter {
+    recent: VecDeque<u16>,
+    pending: Option<BikeSample>,
+    held: Vec<BikeSample>,
+}
+
+impl SpikeFilter {
+    pub fn push(&mut self, sample: BikeSample, threshold: u16) -> Vec<BikeSample> {
+        let mut out = Vec::new();
+
+        let Some(power) = sample.power else {
+            if self.pending.is_some() {
+                self.held.push(sample);
+            } else {
+                out.push(sample);
+            }
+            return out;
+        };
+
+        if let Some(mut pending) = self.pending.take() {
+            let spike = pending.power.unwrap_or(0);
+            if power.abs_diff(spike) <= threshold {
+                self.remember(spike);
+                self.remember(power);
+                out.push(pending);
+                out.append(&mut self.held);
+                out.push(sample);
+                return out;
+            }
+
+            warn!("Rejecting power spike of {} W", spike);
+            pending.power = None;
+            out.push(pending);
+            out.append(&mut self.held);
+        }
+
+        match self.median() {
+            Some(median) if power > median.saturating_add(threshold) => {
+                self.pending = Some(sample);
+            }
+            _ => {
+                self.remember(power);
+                out.push(sample);
+            }
+        }
+        out
+    }
+
+    fn remember(&mut self, power: u16) {
+        self.recent.push_back(power);
+        while self.recent.len() > MEDIAN_SAMPLES {
+            self.recent.pop_front();
+        }
+    }
+
+    fn median(&self) -> Option<u16> {
+        let mut sorted: Vec<u16> = self.recent.iter().copied().collect();
+        sorted.sort_unstable();
+        sorted.get(sorted.len() / 2).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: u16 = 300;
+
+    /// Sample `n` of a trace, `n` ends up in the elapsed time to check the order.
+    fn sample(n: u16, power: Option<u16>) -> BikeSample {
+        BikeSample {
+            power,
+            elapsed_time: Some(n),
+            ..Default::default()
+        }
+    }
+
+    fn run(trace: &[Option<u16>]) -> Vec<BikeSample> {
+        let mut filter = SpikeFilter::default();
+        trace
+            .iter()
+            .enumerate()
+            .flat_map(|(n, &power)| filter.push(sample(n as u16, power), THRESHOLD))
+            .collect()
+    }
+
+    fn powers(samples: &[BikeSample]) -> Vec<Option<u16>> {
+        samples.iter().map(|sample| sample.power).collect()
+    }
+
+    #[test]
+    fn drops_a_one_sample_spike() {
+        let out = run(&[
+            Some(200),
+            Some(210),
+            Some(205),
+            Some(1800),
+            Some(200),
+            Some(205),
+        ]);
+        assert_eq!(
+            powers(&out),
+            [Some(200), Some(210), Some(205), None, Some(200), Some(205)]
+        );
+    }
+
+    #[test]
+    fn passes_a_sustained_sprint_through() {
+        let trace = [
+            Some(200),
+            Some(210),
+            Some(205),
+            Some(900),
+            Some(950),
+            Some(920),
+            Some(940),
+        ];
+        assert_eq!(powers(&run(&trace)), trace);
+    }
+
+    #[test]
+    fn preserves_order_while_a_spike_is_pending() {
+        let out = run(&[
+            Some(200),
+            Some(210),
+            Some(1800),
+            None,
+            None,
+            Some(205),
+            Some(900),
+            None,
+        ]);
+        let order: Vec<_> = out
+            .iter()
+            .map(|sample| sample.elapsed_time.unwrap())
+            .collect();
+        assert_eq!(order, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            powers(&out),
+            [Some(200), Some(210), None, None, None, Some(205)]
+        );
+
+        let out = run(&[Some(200), Some(210), Some(900), None, Some(920), None]);
+        let order: Vec<_> = out
+            .iter()
+            .map(|sample| sample.elapsed_time.unwrap())
+            .collect();
+        assert_eq!(order, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            powers(&out),
+            [Some(200), Some(210), Some(900), None, Some(920), None]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use tokio::{sync::mpsc::Sender, time};
+use tracing::error;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum TimerEvent {
+    Work { round: u32, until: Instant },
+    Rest { round: u32, until: Instant },
+    Done,
+}
+
+/// Alternating work and rest intervals, without the rest after the last round.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct IntervalTimer {
+    pub work: Duration,
+    pub rest: Duration,
+    pub repeats: u32,
+}
+
+impl IntervalTimer {
+    /// Sends an event at the start of each interval and `Done` once the last one is over.
+    pub async fn run(self, tx: Sender<TimerEvent>, ctx: egui::Context) {
+        // Each interval starts when the previous one was due to end so rounds don't drift.
+        let mut start = time::Instant::now();
+
+        for round in 1..=self.repeats {
+            let mut phases = vec![(self.work, true)];
+            if round < self.repeats {
+                phases.push((self.rest, false));
+            }
+
+            for (duration, work) in phases {
+                let until = start + duration;
+                let event = if work {
+                    TimerEvent::Work {
+                        round,
+                        until: until.into_std(),
+                    }
+                } else {
+                    TimerEvent::Rest {
+                        round,
+                        until: until.into_std(),
+                    }
+                };
+
+                if let Err(_) = tx.send(event).await {
+                    error!("Channel closed");
+                    return;
+                }
+                ctx.request_repaint();
+
+                time::sleep_until(until).await;
+                start = until;
+            }
+        }
+
+        let _ = tx.send(TimerEvent::Done).await;
+        ctx.request_repaint();
+    }
+}
@@ -1,15 +1,216 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    future::Future,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use bluest::{
-    btuuid::{self, characteristics::INDOOR_BIKE_DATA, services::FITNESS_MACHINE},
-    Adapter, AdvertisingDevice,
+    btuuid::{
+        characteristics::{
+            BATTERY_LEVEL, CSC_MEASUREMENT, CYCLING_POWER_MEASUREMENT,
+            FIRMWARE_REVISION_STRING, FITNESS_MACHINE_CONTROL_POINT, FITNESS_MACHINE_FEATURE,
+            FITNESS_MACHINE_STATUS, HARDWARE_REVISION_STRING, HEART_RATE_MEASUREMENT,
+            INDOOR_BIKE_DATA, MANUFACTURER_NAME_STRING, MODEL_NUMBER_STRING, SERIAL_NUMBER_STRING,
+            SUPPORTED_INCLINATION_RANGE, SUPPORTED_POWER_RANGE, SUPPORTED_RESISTANCE_LEVEL_RANGE,
+            TRAINING_STATUS, WEIGHT,
+        },
+        services::{
+            BATTERY, CYCLING_POWER, CYCLING_SPEED_AND_CADENCE, DEVICE_INFORMATION,
+            FITNESS_MACHINE, HEART_RATE, USER_DATA,
+        },
+    },
+    Adapter, AdvertisingDevice, Characteristic, ErrorKind, Service, Uuid,
 };
 use eframe::egui;
 use futures_lite::{Stream, StreamExt};
-use tokio::sync::mpsc::{self, Receiver};
-use tracing::error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot, Mutex,
+    },
+    time::{self, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    ftms::{
+        self, BikeSample, InclinationRange, MachineFeatures, MachineStatus, PowerRange,
+        ResistanceRange, SpinDownTarget, TrainingStatus,
+    },
+    grade::GradeSmoother,
+};
+#[cfg(feature = "serde")]
+use crate::session::Session;
+
+// Typical road tyre rolling resistance and rider frontal drag (kg/m).
+pub(crate) const DEFAULT_CRR: f32 = 0.004;
+pub(crate) const DEFAULT_CW: f32 = 0.51;
+
+const RAW_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// A wheel sensor without a new revolution for this long is reported as stopped.
+const WHEEL_STOPPED_AFTER: Duration = Duration::from_secs(3);
+// For devices whose battery level doesn't notify.
+const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(300);
+// How long to wait for a control point response before giving up on the request.
+const CONTROL_POINT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Fitness Machine Control Point op codes (FTMS 4.16.1)
+const REQUEST_CONTROL: u8 = 0x00;
+const RESET: u8 = 0x01;
+const SET_TARGET_RESISTANCE_LEVEL: u8 = 0x04;
+const SET_TARGET_POWER: u8 = 0x05;
+const START_OR_RESUME: u8 = 0x07;
+const STOP_OR_PAUSE: u8 = 0x08;
+const SET_INDOOR_BIKE_SIMULATION: u8 = 0x11;
+const SPIN_DOWN_CONTROL: u8 = 0x13;
+const RESPONSE_CODE: u8 = 0x80;
+const OP_CODE_NAMES: [(u8, &str); 8] = [
+    (REQUEST_CONTROL, "control request"),
+    (RESET, "reset"),
+    (SET_TARGET_RESISTANCE_LEVEL, "target resistance"),
+    (SET_TARGET_POWER, "target power"),
+    (START_OR_RESUME, "start"),
+    (STOP_OR_PAUSE, "pause"),
+    (SET_INDOOR_BIKE_SIMULATION, "simulation parameters"),
+    (SPIN_DOWN_CONTROL, "spin-down"),
+];
+
+// Stop or Pause parameter values (FTMS 4.16.2.9)
+const PAUSE: u8 = 0x02;
+
+// Spin Down Control parameter values (FTMS 4.16.2.20)
+const SPIN_DOWN_START: u8 = 0x01;
+
+// Fitness Machine Control Point result codes (FTMS 4.16.2.22)
+const RESULT_SUCCESS: u8 = 0x01;
+const RESULT_NAMES: [(u8, &str); 4] = [
+    (0x02, "Op Code Not Supported"),
+    (0x03, "Invalid Parameter"),
+    (0x04, "Operation Failed"),
+    (0x05, "Control Not Permitted"),
+];
+
+#[derive(Debug)]
+pub(crate) enum ControlError {
+    Bluetooth(bluest::Error),
+    Rejected { op_code: u8, result: u8 },
+    Timeout { op_code: u8 },
+    InvalidResponse(ftms::ParseError),
+}
+
+fn op_code_name(op_code: u8) -> &'static str {
+    OP_CODE_NAMES
+        .iter()
+        .find(|(code, _)| *code == op_code)
+        .map_or("request", |(_, name)| *name)
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlError::Bluetooth(e) => write!(f, "Bluetooth error: {}", e),
+            ControlError::Rejected { op_code, result } => {
+                let result = RESULT_NAMES
+                    .iter()
+                    .find(|(code, _)| code == result)
+                    .map_or("Unknown Result", |(_, name)| *name);
+                write!(f, "Trainer rejected {} ({})", op_code_name(*op_code), result)
+            }
+            ControlError::Timeout { op_code } => {
+                write!(f, "Trainer did not respond to {}", op_code_name(*op_code))
+            }
+            ControlError::InvalidResponse(e) => write!(f, "Invalid trainer response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+impl From<bluest::Error> for ControlError {
+    fn from(e: bluest::Error) -> Self {
+        ControlError::Bluetooth(e)
+    }
+}
+
+impl From<ErrorKind> for ControlError {
+    fn from(kind: ErrorKind) -> Self {
+        ControlError::Bluetooth(kind.into())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ScanOptions {
+    pub include_sensors: bool,
+    pub name_prefix: Option<String>,
+    /// Only report the device with this id, as saved in the settings.
+    pub device_id: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct ReconnectPolicy {
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+enum StreamEnd {
+    Disconnected,
+    ChannelClosed,
+}
+
+/// A control point request, queued through the sender returned by `BT::spawn_control_queue`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ControlCommand {
+    TargetPower(u16),
+    TargetResistance(f32),
+    Simulation {
+        grade: f32,
+        wind_mps: f32,
+        crr: f32,
+        cw: f32,
+        /// Ease into steeper grades at this many % per second, `None` applies them at once.
+        max_grade_rate: Option<f32>,
+    },
+    RequestControl,
+    Reset,
+    Start,
+    Pause,
+    SpinDown,
+}
+
+impl ControlCommand {
+    /// Whether `self` makes a still queued `other` pointless, only the newest target matters.
+    fn supersedes(&self, other: &ControlCommand) -> bool {
+        matches!(
+            (self, other),
+            (ControlCommand::TargetPower(_), ControlCommand::TargetPower(_))
+                | (ControlCommand::TargetResistance(_), ControlCommand::TargetResistance(_))
+                | (ControlCommand::Simulation { .. }, ControlCommand::Simulation { .. })
+        )
+    }
+
+    /// Whether the trainer leaves simulation mode, or restarts, on this command.
+    fn ends_simulation(&self) -> bool {
+        matches!(
+            self,
+            ControlCommand::TargetPower(_)
+                | ControlCommand::TargetResistance(_)
+                | ControlCommand::Reset
+                | ControlCommand::SpinDown
+        )
+    }
+}
 
 #[derive(Clone)]
 pub(crate) struct BT {
     adapter: Adapter,
+    control_point: Arc<Mutex<Option<Characteristic>>>,
 }
 
 impl BT {
@@ -19,62 +220,1434 @@ impl BT {
             .ok_or("Bluetooth adapter not found")
             .unwrap();
         adapter.wait_available().await?;
-        Ok(Self { adapter })
+        Ok(Self {
+            adapter,
+            control_point: Arc::new(Mutex::new(None)),
+        })
     }
 
     pub async fn discover_devices<'a>(
         &'a mut self,
+        options: ScanOptions,
     ) -> Result<impl Stream<Item = AdvertisingDevice> + 'a, bluest::Error> {
-        let services = &[btuuid::services::FITNESS_MACHINE];
-        self.adapter.scan(services).await
+        let services: &'static [Uuid] = if options.device_id.is_some() {
+            // A known device is matched on its id, whatever it advertises.
+            &[]
+        } else if options.include_sensors {
+            &[FITNESS_MACHINE, CYCLING_POWER, HEART_RATE, CYCLING_SPEED_AND_CADENCE]
+        } else {
+            &[FITNESS_MACHINE, CYCLING_POWER]
+        };
+        let devices = self.adapter.scan(services).await?;
+
+        Ok(devices.filter(move |device| {
+            let id_matches = match &options.device_id {
+                Some(id) => *id == device_address(device),
+                None => true,
+            };
+            let name_matches = match &options.name_prefix {
+                Some(prefix) => device
+                    .device
+                    .name()
+                    .map_or(false, |name| name.starts_with(prefix.as_str())),
+                None => true,
+            };
+            id_matches && name_matches
+        }))
     }
 
     pub async fn connect(
         &self,
         device: AdvertisingDevice,
+        tx: Sender<TrainerUpdate>,
         ctx: egui::Context,
-    ) -> Result<Receiver<TrainerUpdate>, bluest::Error> {
+        raw_log_dir: Option<PathBuf>,
+        reconnect: ReconnectPolicy,
+        system_weight_kg: f32,
+    ) -> Result<(), bluest::Error> {
         self.adapter.connect_device(&device.device).await?;
 
-        let (tx, rx) = mpsc::channel(1024);
+        let bt = self.clone();
 
         tokio::spawn(async move {
-            let services = device.device.services().await.unwrap();
-            let ftms = services
-                .iter()
-                .find(|s| s.uuid() == FITNESS_MACHINE)
-                .unwrap();
+            let mut raw_log = raw_log_dir.and_then(|dir| match RawLog::create(&dir) {
+                Ok(log) => Some(log),
+                Err(e) => {
+                    error!("Failed to create raw data log in {}: {}", dir.display(), e);
+                    None
+                }
+            });
+
+            loop {
+                let stream =
+                    bt.stream_indoor_bike_data(&device, &tx, &ctx, &mut raw_log, system_weight_kg);
+                match stream.await {
+                    Ok(StreamEnd::ChannelClosed) => {
+                        error!("Channel closed");
+                        break;
+                    }
+                    Ok(StreamEnd::Disconnected) => warn!("Trainer disconnected"),
+                    Err(e) => error!("Trainer connection failed: {}", e),
+                }
+
+                *bt.control_point.lock().await = None;
+                if let Err(_) = tx.send(TrainerUpdate::Disconnected).await {
+                    break;
+                }
+                ctx.request_repaint();
+
+                let reconnected = bt.reconnect(&device, reconnect).await;
+                let update = if reconnected {
+                    TrainerUpdate::Reconnected
+                } else {
+                    TrainerUpdate::ReconnectFailed
+                };
+                if let Err(_) = tx.send(update).await {
+                    break;
+                }
+                ctx.request_repaint();
+                if !reconnected {
+                    break;
+                }
+            }
+
+            if let Some(log) = raw_log {
+                if let Err(e) = log.finish() {
+                    error!("Failed to flush raw data log: {}", e);
+                }
+            }
+        });
 
-            let characteristics = ftms.characteristics().await.unwrap();
+        Ok(())
+    }
 
-            let bike_data = characteristics
+    /// Sets the trainer up and forwards its Indoor Bike Data until the notification stream ends,
+    /// which is what happens when the trainer disconnects.
+    async fn stream_indoor_bike_data(
+        &self,
+        device: &AdvertisingDevice,
+        tx: &Sender<TrainerUpdate>,
+        ctx: &egui::Context,
+        raw_log: &mut Option<RawLog>,
+        system_weight_kg: f32,
+    ) -> Result<StreamEnd, bluest::Error> {
+        let services = device.device.services().await?;
+        if let Some(battery) = services.iter().find(|s| s.uuid() == BATTERY) {
+            tokio::spawn(monitor_battery(battery.clone(), tx.clone(), ctx.clone()));
+        }
+        let Some(fitness_machine) = services.iter().find(|s| s.uuid() == FITNESS_MACHINE) else {
+            // Power meters, and dumb trainers with one, only have the Cycling Power Service.
+            let cycling_power = services
                 .iter()
-                .find(|c| c.uuid() == INDOOR_BIKE_DATA)
-                .unwrap();
+                .find(|s| s.uuid() == CYCLING_POWER)
+                .ok_or(ErrorKind::NotFound)?;
+            info!("No fitness machine service, reading power from the Cycling Power Service");
+            *self.control_point.lock().await = None;
+            return stream_cycling_power(cycling_power, tx, ctx).await;
+        };
+
+        let characteristics = fitness_machine.characteristics().await?;
+
+        let bike_data = characteristics
+            .iter()
+            .find(|c| c.uuid() == INDOOR_BIKE_DATA)
+            .ok_or(ErrorKind::NotFound)?;
+
+        let characteristic = characteristics
+            .iter()
+            .find(|c| c.uuid() == FITNESS_MACHINE_CONTROL_POINT)
+            .cloned();
+
+        // The trainer ignores target writes until the client has taken control and started it.
+        if let Some(characteristic) = &characteristic {
+            for command in [REQUEST_CONTROL, START_OR_RESUME] {
+                if let Err(e) = write_control_point(characteristic, &[command]).await {
+                    error!("Control point op code {:#04x} failed: {}", command, e);
+                    if let Err(_) = tx.send(TrainerUpdate::ControlError(e.to_string())).await {
+                        return Ok(StreamEnd::ChannelClosed);
+                    }
+                    break;
+                }
+                if command == REQUEST_CONTROL {
+                    if let Err(_) = tx.send(TrainerUpdate::ControlGranted).await {
+                        return Ok(StreamEnd::ChannelClosed);
+                    }
+                }
+            }
+        }
+        *self.control_point.lock().await = characteristic;
+
+        if let Some(status) = characteristics
+            .iter()
+            .find(|c| c.uuid() == FITNESS_MACHINE_STATUS)
+            .cloned()
+        {
+            tokio::spawn(forward_notifications(
+                status,
+                tx.clone(),
+                ctx.clone(),
+                |bytes| ftms::parse_machine_status(bytes).map(TrainerUpdate::Status),
+            ));
+        }
+
+        if let Some(training_status) = characteristics
+            .iter()
+            .find(|c| c.uuid() == TRAINING_STATUS)
+            .cloned()
+        {
+            // Notifications only arrive on change, so start from the current phase.
+            match training_status
+                .read()
+                .await
+                .map(|bytes| ftms::parse_training_status(&bytes))
+            {
+                Ok(Ok(status)) => {
+                    if let Err(_) = tx.send(TrainerUpdate::TrainingStatus(status)).await {
+                        return Ok(StreamEnd::ChannelClosed);
+                    }
+                }
+                Ok(Err(e)) => warn!("Invalid training status: {}", e),
+                Err(e) => warn!("Failed to read training status: {}", e),
+            }
+
+            tokio::spawn(forward_notifications(
+                training_status,
+                tx.clone(),
+                ctx.clone(),
+                |bytes| match ftms::parse_training_status(bytes) {
+                    Ok(status) => Some(TrainerUpdate::TrainingStatus(status)),
+                    Err(e) => {
+                        warn!("Failed to parse training status: {}", e);
+                        None
+                    }
+                },
+            ));
+        }
+
+        // Trainers use the rider plus bike weight to turn simulated grade into resistance.
+        if let Some(user_data) = services.iter().find(|s| s.uuid() == USER_DATA) {
+            if let Err(e) = write_system_weight(user_data, system_weight_kg).await {
+                warn!("Failed to set system weight: {}", e);
+            }
+        }
 
-            let mut stream = bike_data.notify().await.unwrap();
+        if let Some(feature) = characteristics
+            .iter()
+            .find(|c| c.uuid() == FITNESS_MACHINE_FEATURE)
+        {
+            match feature.read().await.map(|bytes| ftms::parse_machine_features(&bytes)) {
+                Ok(Ok(features)) => {
+                    if let Err(_) = tx.send(TrainerUpdate::Features(features)).await {
+                        return Ok(StreamEnd::ChannelClosed);
+                    }
+                }
+                Ok(Err(e)) => warn!("Invalid fitness machine feature: {}", e),
+                Err(e) => warn!("Failed to read fitness machine feature: {}", e),
+            }
+        }
+
+        if let Some(range) = characteristics
+            .iter()
+            .find(|c| c.uuid() == SUPPORTED_RESISTANCE_LEVEL_RANGE)
+        {
+            match range.read().await.map(|bytes| ftms::parse_resistance_range(&bytes)) {
+                Ok(Ok(range)) => {
+                    if let Err(_) = tx.send(TrainerUpdate::ResistanceRange(range)).await {
+                        return Ok(StreamEnd::ChannelClosed);
+                    }
+                }
+                Ok(Err(e)) => warn!("Invalid supported resistance level range: {}", e),
+                Err(e) => warn!("Failed to read supported resistance level range: {}", e),
+            }
+        } else {
+            info!("Trainer has no supported resistance level range, resistance control disabled");
+        }
+
+        if let Some(range) = characteristics
+            .iter()
+            .find(|c| c.uuid() == SUPPORTED_POWER_RANGE)
+        {
+            match range.read().await.map(|bytes| ftms::parse_power_range(&bytes)) {
+                Ok(Ok(range)) => {
+                    if let Err(_) = tx.send(TrainerUpdate::PowerRange(range)).await {
+                        return Ok(StreamEnd::ChannelClosed);
+                    }
+                }
+                Ok(Err(e)) => warn!("Invalid supported power range: {}", e),
+                Err(e) => warn!("Failed to read supported power range: {}", e),
+            }
+        }
+
+        if let Some(range) = characteristics
+            .iter()
+            .find(|c| c.uuid() == SUPPORTED_INCLINATION_RANGE)
+        {
+            match range.read().await.map(|bytes| ftms::parse_inclination_range(&bytes)) {
+                Ok(Ok(range)) => {
+                    if let Err(_) = tx.send(TrainerUpdate::InclinationRange(range)).await {
+                        return Ok(StreamEnd::ChannelClosed);
+                    }
+                }
+                Ok(Err(e)) => warn!("Invalid supported inclination range: {}", e),
+                Err(e) => warn!("Failed to read supported inclination range: {}", e),
+            }
+        }
+
+        let mut stream = bike_data.notify().await?;
+
+        while let Some(update) = stream.next().await {
+            if let Ok(update) = update {
+                if let Some(log) = raw_log.as_mut() {
+                    if let Err(e) = log.write(&update) {
+                        error!("Failed to write raw data log: {}", e);
+                        *raw_log = None;
+                    }
+                }
+
+                // Forward the raw payload first so malformed packets still show up in the UI.
+                let raw = TrainerUpdate::RawIndoorBikeData(update.clone());
+                if let Err(_) = tx.send(raw).await {
+                    return Ok(StreamEnd::ChannelClosed);
+                }
+
+                let mut sample = match ftms::parse_indoor_bike_data(&update) {
+                    Ok(sample) => sample,
+                    Err(e) => {
+                        // Skip malformed packets, a bad notification must not end the stream.
+                        warn!("Skipping indoor bike data {:02x?}: {}", update, e);
+                        continue;
+                    }
+                };
+
+                sample.timestamp = Some(SystemTime::now());
+
+                if let Err(_) = tx.send(TrainerUpdate::Sample(sample)).await {
+                    return Ok(StreamEnd::ChannelClosed);
+                }
+
+                ctx.request_repaint();
+            }
+        }
+
+        Ok(StreamEnd::Disconnected)
+    }
+
+    async fn reconnect(&self, device: &AdvertisingDevice, policy: ReconnectPolicy) -> bool {
+        let mut backoff = policy.initial_backoff;
+        for attempt in 1..=policy.attempts {
+            time::sleep(backoff).await;
+            match self.adapter.connect_device(&device.device).await {
+                Ok(()) => {
+                    info!("Reconnected after {} attempt(s)", attempt);
+                    return true;
+                }
+                Err(e) => warn!(
+                    "Reconnect attempt {} of {} failed: {}",
+                    attempt, policy.attempts, e
+                ),
+            }
+            backoff *= 2;
+        }
+        false
+    }
+
+    pub async fn connect_heart_rate(
+        &self,
+        device: AdvertisingDevice,
+        tx: Sender<TrainerUpdate>,
+        ctx: egui::Context,
+    ) -> Result<(), bluest::Error> {
+        self.adapter.connect_device(&device.device).await?;
+
+        let services = device.device.services().await?;
+        let measurement =
+            find_characteristic(&services, HEART_RATE, HEART_RATE_MEASUREMENT).await?;
+        if let Some(battery) = services.iter().find(|s| s.uuid() == BATTERY) {
+            tokio::spawn(monitor_battery(battery.clone(), tx.clone(), ctx.clone()));
+        }
+
+        // The stream borrows the characteristic, so the task subscribes and reports back.
+        let (subscribed_tx, subscribed_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut stream = match measurement.notify().await {
+                Ok(stream) => {
+                    let _ = subscribed_tx.send(Ok(()));
+                    stream
+                }
+                Err(e) => {
+                    let _ = subscribed_tx.send(Err(e));
+                    return;
+                }
+            };
 
             while let Some(update) = stream.next().await {
                 if let Ok(update) = update {
-                    let speed = u16::from_le_bytes([update[2], update[3]]);
-                    let power = u16::from_le_bytes([update[4], update[5]]);
+                    let measurement = match ftms::parse_heart_rate_measurement(&update) {
+                        Ok(measurement) => measurement,
+                        Err(e) => {
+                            warn!("Skipping heart rate measurement {:02x?}: {}", update, e);
+                            continue;
+                        }
+                    };
 
-                    if let Err(_) = tx.send(TrainerUpdate::Power { speed, power }).await {
-                        // Handle the error if the receiver is closed.
+                    let bpm = measurement.bpm;
+                    if let Err(_) = tx.send(TrainerUpdate::HeartRate { bpm }).await {
                         error!("Channel closed");
                         break;
                     }
+                    if !measurement.rr_intervals.is_empty() {
+                        let millis = measurement
+                            .rr_intervals
+                            .iter()
+                            .map(|rr| (*rr as u32 * 1000 / 1024) as u16)
+                            .collect();
+                        if let Err(_) = tx.send(TrainerUpdate::RrIntervals { millis }).await {
+                            error!("Channel closed");
+                            break;
+                        }
+                    }
 
                     ctx.request_repaint();
                 }
             }
         });
 
+        subscribed_rx
+            .await
+            .unwrap_or_else(|_| Err(ErrorKind::NotConnected.into()))
+    }
+
+    pub async fn connect_cadence_sensor(
+        &self,
+        device: AdvertisingDevice,
+        tx: Sender<TrainerUpdate>,
+        ctx: egui::Context,
+        wheel_circumference_mm: u16,
+    ) -> Result<(), bluest::Error> {
+        self.adapter.connect_device(&device.device).await?;
+
+        let services = device.device.services().await?;
+        let measurement =
+            find_characteristic(&services, CYCLING_SPEED_AND_CADENCE, CSC_MEASUREMENT).await?;
+        if let Some(battery) = services.iter().find(|s| s.uuid() == BATTERY) {
+            tokio::spawn(monitor_battery(battery.clone(), tx.clone(), ctx.clone()));
+        }
+
+        let (subscribed_tx, subscribed_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut stream = match measurement.notify().await {
+                Ok(stream) => {
+                    let _ = subscribed_tx.send(Ok(()));
+                    stream
+                }
+                Err(e) => {
+                    let _ = subscribed_tx.send(Err(e));
+                    return;
+                }
+            };
+            // Speed and cadence are worked out between notifications, per sensor.
+            let mut cadence = CrankCadence::default();
+            let mut speed = WheelSpeed::new(wheel_circumference_mm);
+
+            while let Some(update) = stream.next().await {
+                if let Ok(update) = update {
+                    let measurement = match ftms::parse_csc_measurement(&update) {
+                        Ok(measurement) => measurement,
+                        Err(e) => {
+                            warn!("Skipping CSC measurement {:02x?}: {}", update, e);
+                            continue;
+                        }
+                    };
+
+                    let kmh = measurement
+                        .wheel
+                        .and_then(|wheel| speed.update(Instant::now(), wheel));
+                    if let Some(kmh) = kmh {
+                        let sample = BikeSample {
+                            timestamp: Some(SystemTime::now()),
+                            speed: Some(kmh),
+                            ..Default::default()
+                        };
+                        if let Err(_) = tx.send(TrainerUpdate::Sample(sample)).await {
+                            error!("Channel closed");
+                            break;
+                        }
+                    }
+
+                    if let Some(rpm) = measurement.crank.and_then(|crank| cadence.update(crank)) {
+                        if let Err(_) = tx.send(TrainerUpdate::Cadence { rpm }).await {
+                            error!("Channel closed");
+                            break;
+                        }
+                    }
+
+                    ctx.request_repaint();
+                }
+            }
+        });
+
+        subscribed_rx
+            .await
+            .unwrap_or_else(|_| Err(ErrorKind::NotConnected.into()))
+    }
+
+    pub async fn disconnect(&self, device: &AdvertisingDevice) -> Result<(), bluest::Error> {
+        self.adapter.disconnect_device(&device.device).await
+    }
+
+    #[cfg(feature = "serde")]
+    pub async fn playback(path: &Path) -> bincode::Result<Receiver<TrainerUpdate>> {
+        let session = Session::load(path)?;
+        let (tx, rx) = mpsc::channel(1024);
+
+        tokio::spawn(async move {
+            let start = Instant::now();
+
+            for (offset, update) in session.into_events() {
+                time::sleep_until(start + offset).await;
+
+                if let Err(_) = tx.send(update).await {
+                    error!("Channel closed");
+                    break;
+                }
+            }
+        });
+
         Ok(rx)
     }
+
+    /// Spawns the task that owns control point writes and returns the sender feeding it.
+    ///
+    /// Commands run one at a time, each waiting for its response before the next is written,
+    /// since trainers reject or drop requests sent while another is outstanding. Failures and
+    /// responses the UI cares about come back through `updates`.
+    ///
+    /// Simulation parameters are written at most once per `simulation_interval`, dragging the
+    /// grade or following a course would otherwise overwhelm the trainer. Grades eased in by
+    /// the smoother are stepped toward their target on the same schedule.
+    pub fn spawn_control_queue(
+        &self,
+        updates: Sender<TrainerUpdate>,
+        simulation_interval: Duration,
+    ) -> Sender<ControlCommand> {
+        let (tx, rx) = mpsc::channel(64);
+        let bt = self.clone();
+
+        tokio::spawn(run_control_queue(
+            rx,
+            updates,
+            simulation_interval,
+            move |command| {
+                let bt = bt.clone();
+                async move { bt.execute(command).await }
+            },
+        ));
+
+        tx
+    }
+
+    async fn execute(
+        &self,
+        command: ControlCommand,
+    ) -> Result<Option<TrainerUpdate>, ControlError> {
+        match command {
+            ControlCommand::TargetPower(watts) => self.set_target_power(watts).await?,
+            ControlCommand::TargetResistance(level) => self.set_target_resistance(level).await?,
+            ControlCommand::Simulation {
+                grade,
+                wind_mps,
+                crr,
+                cw,
+                ..
+            } => self.set_sim_params(grade, wind_mps, crr, cw).await?,
+            ControlCommand::RequestControl => {
+                self.request_control().await?;
+                return Ok(Some(TrainerUpdate::ControlGranted));
+            }
+            ControlCommand::Reset => self.reset().await?,
+            ControlCommand::Start => self.start().await?,
+            ControlCommand::Pause => self.pause().await?,
+            ControlCommand::SpinDown => {
+                let target = self.start_spin_down().await?;
+                return Ok(Some(TrainerUpdate::SpinDownStarted(target)));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn set_target_power(&self, watts: u16) -> Result<(), ControlError> {
+        let [lo, hi] = (watts.min(i16::MAX as u16) as i16).to_le_bytes();
+        self.write_control_point(&[SET_TARGET_POWER, lo, hi]).await?;
+        Ok(())
+    }
+
+    async fn set_target_resistance(&self, level: f32) -> Result<(), ControlError> {
+        // Resistance level is a sint16 with a resolution of 0.1.
+        let [lo, hi] = ((level * 10.0).round() as i16).to_le_bytes();
+        self.write_control_point(&[SET_TARGET_RESISTANCE_LEVEL, lo, hi]).await?;
+        Ok(())
+    }
+
+    async fn set_sim_params(
+        &self,
+        grade_pct: f32,
+        wind_mps: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<(), ControlError> {
+        // Wind speed (0.001 m/s) and grade (0.01 %) are sint16, Crr (0.0001) and Cw (0.01 kg/m)
+        // are uint8.
+        let [wind_lo, wind_hi] = ((wind_mps * 1000.0).round() as i16).to_le_bytes();
+        let [grade_lo, grade_hi] = ((grade_pct * 100.0).round() as i16).to_le_bytes();
+        let crr = (crr * 10000.0).round().clamp(0.0, u8::MAX as f32) as u8;
+        let cw = (cw * 100.0).round().clamp(0.0, u8::MAX as f32) as u8;
+
+        self.write_control_point(&[
+            SET_INDOOR_BIKE_SIMULATION,
+            wind_lo,
+            wind_hi,
+            grade_lo,
+            grade_hi,
+            crr,
+            cw,
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn request_control(&self) -> Result<(), ControlError> {
+        self.write_control_point(&[REQUEST_CONTROL]).await?;
+        Ok(())
+    }
+
+    async fn reset(&self) -> Result<(), ControlError> {
+        self.write_control_point(&[RESET]).await?;
+        // Some trainers drop the control grant on reset.
+        self.write_control_point(&[REQUEST_CONTROL]).await?;
+        Ok(())
+    }
+
+    async fn start(&self) -> Result<(), ControlError> {
+        self.write_control_point(&[START_OR_RESUME]).await?;
+        Ok(())
+    }
+
+    async fn pause(&self) -> Result<(), ControlError> {
+        self.write_control_point(&[STOP_OR_PAUSE, PAUSE]).await?;
+        Ok(())
+    }
+
+    /// Starts a spin down calibration, returning the speed window the rider has to reach.
+    async fn start_spin_down(&self) -> Result<SpinDownTarget, ControlError> {
+        let response = self
+            .write_control_point(&[SPIN_DOWN_CONTROL, SPIN_DOWN_START])
+            .await?;
+        ftms::parse_spin_down_target(&response).map_err(ControlError::InvalidResponse)
+    }
+
+    async fn write_control_point(&self, command: &[u8]) -> Result<Vec<u8>, ControlError> {
+        let control_point = self.control_point.lock().await;
+        let control_point = control_point.as_ref().ok_or(ErrorKind::NotConnected)?;
+        write_control_point(control_point, command).await
+    }
 }
 
-#[derive(Debug)]
+/// Grade to send the trainer for a course `grade`, climbs scaled by the trainer difficulty
+/// (0.0-1.0). Descents go through unscaled so coasting downhill still feels fast.
+/// The virtual gear offset is added after scaling so a shift feels the same at any difficulty.
+pub(crate) fn trainer_grade(grade: f32, difficulty: f32, gear_offset: f32) -> f32 {
+    let scaled = if grade > 0.0 {
+        grade * difficulty.clamp(0.0, 1.0)
+    } else {
+        grade
+    };
+    scaled + gear_offset
+}
+
+/// Runs the control queue behind `BT::spawn_control_queue`, writing commands with `execute`.
+async fn run_control_queue<F, Fut>(
+    mut rx: Receiver<ControlCommand>,
+    updates: Sender<TrainerUpdate>,
+    simulation_interval: Duration,
+    mut execute: F,
+) where
+    F: FnMut(ControlCommand) -> Fut,
+    Fut: Future<Output = Result<Option<TrainerUpdate>, ControlError>>,
+{
+    let mut pending = VecDeque::new();
+    let mut last_simulation: Option<Instant> = None;
+    let mut smoother = GradeSmoother::new(0.0);
+    // Simulation command whose grade the smoother hasn't reached yet.
+    let mut easing = None;
+
+    loop {
+        if pending.is_empty() {
+            if let Some(command) = easing.take() {
+                pending.push_back(command);
+            } else {
+                let Some(command) = rx.recv().await else {
+                    break;
+                };
+                enqueue(&mut pending, command);
+            }
+        }
+        // Coalesce whatever was queued while the previous command was in flight.
+        while let Ok(command) = rx.try_recv() {
+            enqueue(&mut pending, command);
+        }
+
+        // Hold back a simulation update that comes too soon, newer ones replace it.
+        let simulation = matches!(pending.front(), Some(ControlCommand::Simulation { .. }));
+        let due = last_simulation
+            .map(|sent| sent + simulation_interval)
+            .filter(|due| simulation && Instant::now() < *due);
+        if let Some(due) = due {
+            tokio::select! {
+                _ = time::sleep_until(due) => {}
+                command = rx.recv() => match command {
+                    Some(command) => enqueue(&mut pending, command),
+                    None => break,
+                },
+            }
+            continue;
+        }
+
+        let Some(mut command) = pending.pop_front() else {
+            continue;
+        };
+        if command.ends_simulation() {
+            easing = None;
+        }
+        if let ControlCommand::Simulation {
+            grade: target,
+            wind_mps,
+            crr,
+            cw,
+            max_grade_rate,
+        } = command
+        {
+            let now = Instant::now();
+            // Each write moves the grade on by at most one interval's worth.
+            let dt = last_simulation.map_or(Duration::ZERO, |sent| {
+                now.duration_since(sent).min(simulation_interval)
+            });
+            last_simulation = Some(now);
+
+            let grade = match max_grade_rate {
+                Some(rate) => {
+                    smoother.max_rate = rate;
+                    smoother.tick(dt, target)
+                }
+                None => {
+                    smoother.set(target);
+                    target
+                }
+            };
+            easing = (grade != target).then_some(command);
+            command = ControlCommand::Simulation {
+                grade,
+                wind_mps,
+                crr,
+                cw,
+                max_grade_rate,
+            };
+        }
+        let update = match execute(command).await {
+            Ok(update) => update,
+            Err(e) => {
+                error!("Control command {:?} failed: {}", command, e);
+                Some(TrainerUpdate::ControlError(e.to_string()))
+            }
+        };
+        if let Some(update) = update {
+            if let Err(_) = updates.send(update).await {
+                error!("Channel closed");
+                break;
+            }
+        }
+    }
+}
+
+/// Queues `command` behind the pending ones, dropping those it supersedes.
+fn enqueue(pending: &mut VecDeque<ControlCommand>, command: ControlCommand) {
+    pending.retain(|queued| !command.supersedes(queued));
+    pending.push_back(command);
+}
+
+/// Stable identifier for a device, as stored in the settings.
+pub(crate) fn device_address(device: &AdvertisingDevice) -> String {
+    format!("{:?}", device.device.id())
+}
+
+/// Turns cumulative crank revolution data into cadence.
+#[derive(Default)]
+struct CrankCadence {
+    last: Option<(u16, u16)>,
+}
+
+impl CrankCadence {
+    /// Returns the cadence since the previous reading, `None` without a new crank event.
+    fn update(&mut self, crank: (u16, u16)) -> Option<f32> {
+        // Both counters wrap, and an unchanged event time means no new revolution.
+        let (last_revolutions, last_event_time) = self.last.replace(crank)?;
+        let (revolutions, event_time) = crank;
+        let elapsed = event_time.wrapping_sub(last_event_time);
+        if elapsed == 0 {
+            return None;
+        }
+        Some(revolutions.wrapping_sub(last_revolutions) as f32 * 60.0 * 1024.0 / elapsed as f32)
+    }
+}
+
+/// Turns cumulative wheel revolution data into speed.
+struct WheelSpeed {
+    circumference_m: f32,
+    last: Option<(u32, u16)>,
+    last_event: Option<Instant>,
+}
+
+impl WheelSpeed {
+    fn new(circumference_mm: u16) -> Self {
+        Self {
+            circumference_m: circumference_mm as f32 / 1000.0,
+            last: None,
+            last_event: None,
+        }
+    }
+
+    /// Returns the speed in km/h since the previous reading. Sensors keep repeating the last
+    /// event while the wheel is still, which reads as 0 once it has gone on for a while.
+    fn update(&mut self, now: Instant, wheel: (u32, u16)) -> Option<f32> {
+        let (last_revolutions, last_event_time) = self.last.replace(wheel)?;
+        let (revolutions, event_time) = wheel;
+        let elapsed = event_time.wrapping_sub(last_event_time);
+        if elapsed == 0 {
+            let stopped = self
+                .last_event
+                .map_or(false, |last| now.duration_since(last) >= WHEEL_STOPPED_AFTER);
+            return stopped.then_some(0.0);
+        }
+        self.last_event = Some(now);
+
+        let meters = revolutions.wrapping_sub(last_revolutions) as f32 * self.circumference_m;
+        let secs = elapsed as f32 / 1024.0;
+        Some(meters / secs * 3.6)
+    }
+}
+
+/// Forwards Cycling Power Measurements as samples, for devices without a fitness machine.
+async fn stream_cycling_power(
+    service: &Service,
+    tx: &Sender<TrainerUpdate>,
+    ctx: &egui::Context,
+) -> Result<StreamEnd, bluest::Error> {
+    let characteristics = service.characteristics().await?;
+    let measurement = characteristics
+        .iter()
+        .find(|c| c.uuid() == CYCLING_POWER_MEASUREMENT)
+        .ok_or(ErrorKind::NotFound)?;
+
+    let mut stream = measurement.notify().await?;
+    let mut cadence = CrankCadence::default();
+
+    while let Some(update) = stream.next().await {
+        if let Ok(update) = update {
+            let measurement = match ftms::parse_cycling_power_measurement(&update) {
+                Ok(measurement) => measurement,
+                Err(e) => {
+                    warn!("Skipping cycling power measurement {:02x?}: {}", update, e);
+                    continue;
+                }
+            };
+
+            let sample = BikeSample {
+                timestamp: Some(SystemTime::now()),
+                power: Some(measurement.power.max(0) as u16),
+                cadence: measurement.crank.and_then(|crank| cadence.update(crank)),
+                ..Default::default()
+            };
+            if let Err(_) = tx.send(TrainerUpdate::Sample(sample)).await {
+                return Ok(StreamEnd::ChannelClosed);
+            }
+
+            ctx.request_repaint();
+        }
+    }
+
+    Ok(StreamEnd::Disconnected)
+}
+
+/// Writes `command` and waits for its response indication, returning the response parameters.
+///
+/// Callers hold the control point lock for the whole exchange, so at most one request is
+/// outstanding and the response can be matched on its request op code alone.
+async fn write_control_point(
+    control_point: &Characteristic,
+    command: &[u8],
+) -> Result<Vec<u8>, ControlError> {
+    let op_code = command[0];
+    time::timeout(CONTROL_POINT_TIMEOUT, send_control_point(control_point, command))
+        .await
+        .unwrap_or(Err(ControlError::Timeout { op_code }))
+}
+
+async fn send_control_point(
+    control_point: &Characteristic,
+    command: &[u8],
+) -> Result<Vec<u8>, ControlError> {
+    // Subscribe before writing so the response indication can't be missed.
+    let mut indications = control_point.notify().await?;
+    control_point.write(command).await?;
+
+    while let Some(indication) = indications.next().await {
+        let indication = indication?;
+        if indication.len() >= 3 && indication[0] == RESPONSE_CODE && indication[1] == command[0] {
+            if indication[2] == RESULT_SUCCESS {
+                return Ok(indication[3..].to_vec());
+            }
+
+            error!(
+                "Control point op code {:#04x} failed with result code {:#04x}",
+                command[0], indication[2]
+            );
+            return Err(ControlError::Rejected {
+                op_code: command[0],
+                result: indication[2],
+            });
+        }
+    }
+
+    Err(ErrorKind::NotConnected.into())
+}
+
+/// Forwards every notification of `characteristic` that `parse` turns into an update.
+async fn forward_notifications(
+    characteristic: Characteristic,
+    tx: Sender<TrainerUpdate>,
+    ctx: egui::Context,
+    parse: fn(&[u8]) -> Option<TrainerUpdate>,
+) {
+    let mut stream = match characteristic.notify().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("Failed to subscribe to {}: {}", characteristic.uuid(), e);
+            return;
+        }
+    };
+
+    while let Some(update) = stream.next().await {
+        let Ok(update) = update else {
+            continue;
+        };
+        let Some(update) = parse(&update) else {
+            continue;
+        };
+
+        if let Err(_) = tx.send(update).await {
+            error!("Channel closed");
+            break;
+        }
+        ctx.request_repaint();
+    }
+}
+
+/// Looks up a characteristic of a service, failing when the device lacks either.
+async fn find_characteristic(
+    services: &[Service],
+    service: Uuid,
+    characteristic: Uuid,
+) -> Result<Characteristic, bluest::Error> {
+    let service = services
+        .iter()
+        .find(|s| s.uuid() == service)
+        .ok_or(ErrorKind::NotFound)?;
+    let characteristic = service
+        .characteristics()
+        .await?
+        .into_iter()
+        .find(|c| c.uuid() == characteristic)
+        .ok_or(ErrorKind::NotFound)?;
+    Ok(characteristic)
+}
+
+/// What the Device Information Service reported, devices often leave some of it out.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeviceInfo {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl DeviceInfo {
+    pub fn is_empty(&self) -> bool {
+        self.fields().iter().all(|(_, value)| value.is_none())
+    }
+
+    /// Labelled values, in display order.
+    pub fn fields(&self) -> [(&'static str, Option<&str>); 5] {
+        [
+            ("Manufacturer", self.manufacturer.as_deref()),
+            ("Model", self.model.as_deref()),
+            ("Firmware", self.firmware_revision.as_deref()),
+            ("Hardware", self.hardware_revision.as_deref()),
+            ("Serial number", self.serial_number.as_deref()),
+        ]
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<_> = self
+            .fields()
+            .into_iter()
+            .filter_map(|(label, value)| Some(format!("{}: {}", label.to_lowercase(), value?)))
+            .collect();
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+/// Reads the Device Information Service once, leaving out whatever the device doesn't expose.
+pub(crate) async fn read_device_info(device: &AdvertisingDevice) -> DeviceInfo {
+    let mut info = DeviceInfo::default();
+    let services = match device.device.services().await {
+        Ok(services) => services,
+        Err(e) => {
+            warn!("Failed to discover services: {}", e);
+            return info;
+        }
+    };
+    let Some(service) = services.iter().find(|s| s.uuid() == DEVICE_INFORMATION) else {
+        return info;
+    };
+    let characteristics = match service.characteristics().await {
+        Ok(characteristics) => characteristics,
+        Err(e) => {
+            warn!("Failed to discover the device information service: {}", e);
+            return info;
+        }
+    };
+
+    for characteristic in characteristics {
+        let field = match characteristic.uuid() {
+            MANUFACTURER_NAME_STRING => &mut info.manufacturer,
+            MODEL_NUMBER_STRING => &mut info.model,
+            FIRMWARE_REVISION_STRING => &mut info.firmware_revision,
+            HARDWARE_REVISION_STRING => &mut info.hardware_revision,
+            SERIAL_NUMBER_STRING => &mut info.serial_number,
+            _ => continue,
+        };
+        match characteristic.read().await {
+            // Some devices pad their strings with zeros.
+            Ok(bytes) => {
+                let value = String::from_utf8_lossy(&bytes);
+                let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+                *field = (!value.is_empty()).then(|| value.to_string());
+            }
+            Err(e) => warn!("Failed to read {}: {}", characteristic.uuid(), e),
+        }
+    }
+    info
+}
+
+/// Reports the Battery Level, from notifications when the device supports them and otherwise
+/// by reading it again every few minutes, until the device goes away.
+async fn monitor_battery(service: Service, tx: Sender<TrainerUpdate>, ctx: egui::Context) {
+    let level = match service.characteristics().await {
+        Ok(characteristics) => characteristics.into_iter().find(|c| c.uuid() == BATTERY_LEVEL),
+        Err(e) => {
+            warn!("Failed to discover the battery service: {}", e);
+            return;
+        }
+    };
+    let Some(level) = level else {
+        return;
+    };
+
+    // Notifications only arrive on change, so start from the current level.
+    let mut percent = match level.read().await {
+        Ok(bytes) => bytes.first().copied(),
+        Err(e) => {
+            warn!("Failed to read battery level: {}", e);
+            return;
+        }
+    };
+
+    match level.notify().await {
+        Ok(mut stream) => loop {
+            if let Some(percent) = percent {
+                let update = TrainerUpdate::Battery { percent: percent.min(100) };
+                if let Err(_) = tx.send(update).await {
+                    error!("Channel closed");
+                    break;
+                }
+                ctx.request_repaint();
+            }
+            percent = match stream.next().await {
+                Some(Ok(bytes)) => bytes.first().copied(),
+                Some(Err(_)) => None,
+                None => break,
+            };
+        },
+        Err(e) => {
+            info!("Battery level doesn't notify, polling it: {}", e);
+            loop {
+                if let Some(percent) = percent {
+                    let update = TrainerUpdate::Battery { percent: percent.min(100) };
+                    if let Err(_) = tx.send(update).await {
+                        error!("Channel closed");
+                        break;
+                    }
+                    ctx.request_repaint();
+                }
+                time::sleep(BATTERY_POLL_INTERVAL).await;
+                percent = match level.read().await {
+                    Ok(bytes) => bytes.first().copied(),
+                    // The device disconnected.
+                    Err(_) => break,
+                };
+            }
+        }
+    }
+}
+
+async fn write_system_weight(user_data: &Service, weight_kg: f32) -> Result<(), bluest::Error> {
+    let characteristics = user_data.characteristics().await?;
+    let weight = characteristics
+        .iter()
+        .find(|c| c.uuid() == WEIGHT)
+        .ok_or(ErrorKind::NotFound)?;
+
+    // uint16 with a resolution of 0.005 kg.
+    let value = (weight_kg / 0.005).round().clamp(0.0, u16::MAX as f32) as u16;
+    weight.write(&value.to_le_bytes()).await
+}
+
+/// Newline-delimited `<unix millis> <hex payload>` log of raw Indoor Bike Data notifications.
+struct RawLog {
+    writer: BufWriter<File>,
+    last_flush: Instant,
+}
+
+impl RawLog {
+    fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let file = File::create(dir.join(format!("indoor-bike-data-{}.hex", now.as_secs())))?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            last_flush: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(self.writer, "{}", now.as_millis())?;
+        for byte in bytes {
+            write!(self.writer, " {:02x}", byte)?;
+        }
+        writeln!(self.writer)?;
+
+        if self.last_flush.elapsed() >= RAW_LOG_FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DeviceKind {
+    Trainer,
+    HeartRateMonitor,
+    CadenceSensor,
+}
+
+impl DeviceKind {
+    pub fn of(device: &AdvertisingDevice) -> Self {
+        let services = &device.adv_data.services;
+        // A power meter stands in for the trainer, even when it also advertises cadence.
+        if services.contains(&FITNESS_MACHINE) || services.contains(&CYCLING_POWER) {
+            DeviceKind::Trainer
+        } else if services.contains(&HEART_RATE) {
+            DeviceKind::HeartRateMonitor
+        } else if services.contains(&CYCLING_SPEED_AND_CADENCE) {
+            DeviceKind::CadenceSensor
+        } else {
+            DeviceKind::Trainer
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DeviceKind::Trainer => "Trainer",
+            DeviceKind::HeartRateMonitor => "HR",
+            DeviceKind::CadenceSensor => "Cadence",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) enum TrainerUpdate {
-    Power { speed: u16, power: u16 },
+    Sample(BikeSample),
+    RawIndoorBikeData(Vec<u8>),
+    ControlError(String),
+    SpinDownStarted(SpinDownTarget),
+    Status(MachineStatus),
+    TrainingStatus(TrainingStatus),
+    ControlGranted,
+    Disconnected,
+    Reconnected,
+    ReconnectFailed,
+    ResistanceRange(ResistanceRange),
+    PowerRange(PowerRange),
+    InclinationRange(InclinationRange),
+    Features(MachineFeatures),
+    HeartRate {
+        bpm: u8,
+    },
+    Cadence {
+        rpm: f32,
+    },
+    // Kept after the older variants so recorded session files still load.
+    RrIntervals {
+        millis: Vec<u16>,
+    },
+    Battery {
+        percent: u8,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::task::JoinHandle;
+
+    use super::*;
+
+    /// How long the fake trainer takes to answer a control point write.
+    const WRITE_TIME: Duration = Duration::from_millis(20);
+
+    type Written = Arc<std::sync::Mutex<Vec<(Instant, ControlCommand)>>>;
+
+    /// Runs a control queue against a fake trainer, recording every command it writes.
+    fn spawn_queue(
+        simulation_interval: Duration,
+    ) -> (Sender<ControlCommand>, Written, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(64);
+        let (updates, _) = mpsc::channel(64);
+        let written = Written::default();
+        let log = written.clone();
+        let queue = tokio::spawn(run_control_queue(
+            rx,
+            updates,
+            simulation_interval,
+            move |command| {
+                let log = log.clone();
+                async move {
+                    log.lock().unwrap().push((Instant::now(), command));
+                    time::sleep(WRITE_TIME).await;
+                    Ok(None)
+                }
+            },
+        ));
+        (tx, written, queue)
+    }
+
+    fn target_powers(written: &Written) -> Vec<u16> {
+        let written = written.lock().unwrap();
+        written
+            .iter()
+            .map(|(_, command)| match command {
+                ControlCommand::TargetPower(watts) => *watts,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    fn simulation(grade: f32, max_grade_rate: Option<f32>) -> ControlCommand {
+        ControlCommand::Simulation {
+            grade,
+            wind_mps: 0.0,
+            crr: 0.004,
+            cw: 0.51,
+            max_grade_rate,
+        }
+    }
+
+    fn grades(written: &Written) -> Vec<f32> {
+        let written = written.lock().unwrap();
+        written
+            .iter()
+            .map(|(_, command)| match command {
+                ControlCommand::Simulation { grade, .. } => *grade,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_of_target_power_writes_only_the_last() {
+        let (tx, written, queue) = spawn_queue(Duration::from_secs(1));
+        for watts in [100, 150, 200, 250] {
+            tx.send(ControlCommand::TargetPower(watts)).await.unwrap();
+        }
+        drop(tx);
+        queue.await.unwrap();
+
+        assert_eq!(target_powers(&written), [250]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn targets_sent_during_a_write_collapse_to_the_last() {
+        let (tx, written, queue) = spawn_queue(Duration::from_secs(1));
+        tx.send(ControlCommand::TargetPower(100)).await.unwrap();
+        time::sleep(WRITE_TIME / 4).await;
+        for watts in [150, 200, 250] {
+            tx.send(ControlCommand::TargetPower(watts)).await.unwrap();
+        }
+        drop(tx);
+        queue.await.unwrap();
+
+        assert_eq!(target_powers(&written), [100, 250]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulation_writes_respect_the_interval() {
+        let interval = Duration::from_secs(1);
+        let (tx, written, queue) = spawn_queue(interval);
+        // Dragging the grade slider for three seconds.
+        for step in 0..30 {
+            tx.send(simulation(step as f32 * 0.1, None)).await.unwrap();
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        time::sleep(interval * 2).await;
+        drop(tx);
+        queue.await.unwrap();
+
+        let written = written.lock().unwrap();
+        assert!((3..=5).contains(&written.len()), "{} writes", written.len());
+        for pair in written.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= interval);
+        }
+        // The newest grade still goes out once the interval has passed.
+        match written.last().unwrap().1 {
+            ControlCommand::Simulation { grade, .. } => assert_eq!(grade, 29.0 * 0.1),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulation_eases_into_climbs_at_the_max_rate() {
+        let interval = Duration::from_secs(1);
+        let (tx, written, queue) = spawn_queue(interval);
+        tx.send(simulation(0.0, Some(1.0))).await.unwrap();
+        time::sleep(interval * 2).await;
+        tx.send(simulation(8.0, Some(1.0))).await.unwrap();
+        time::sleep(interval * 10).await;
+        tx.send(simulation(2.0, Some(1.0))).await.unwrap();
+        time::sleep(interval * 2).await;
+        drop(tx);
+        queue.await.unwrap();
+
+        // One write per interval, each 1 % steeper, then straight back down.
+        assert_eq!(
+            grades(&written),
+            [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 2.0]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulation_without_max_rate_applies_climbs_at_once() {
+        let interval = Duration::from_secs(1);
+        let (tx, written, queue) = spawn_queue(interval);
+        tx.send(simulation(0.0, None)).await.unwrap();
+        time::sleep(interval * 2).await;
+        tx.send(simulation(8.0, None)).await.unwrap();
+        time::sleep(interval * 2).await;
+        drop(tx);
+        queue.await.unwrap();
+
+        assert_eq!(grades(&written), [0.0, 8.0]);
+    }
+
+    #[test]
+    fn trainer_grade_scales_climbs_by_difficulty() {
+        assert_eq!(trainer_grade(10.0, 1.0, 0.0), 10.0);
+        assert_eq!(trainer_grade(10.0, 0.5, 0.0), 5.0);
+        assert_eq!(trainer_grade(10.0, 0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn trainer_grade_does_not_scale_descents() {
+        assert_eq!(trainer_grade(-6.0, 1.0, 0.0), -6.0);
+        assert_eq!(trainer_grade(-6.0, 0.5, 0.0), -6.0);
+        assert_eq!(trainer_grade(-6.0, 0.0, 0.0), -6.0);
+    }
+
+    #[test]
+    fn trainer_grade_adds_gear_offset_after_scaling() {
+        // Two gears up at 0.5 % a gear.
+        assert_eq!(trainer_grade(10.0, 0.5, 1.0), 6.0);
+        assert_eq!(trainer_grade(10.0, 0.0, 1.0), 1.0);
+        assert_eq!(trainer_grade(0.0, 0.5, -1.0), -1.0);
+        assert_eq!(trainer_grade(-6.0, 0.5, 1.0), -5.0);
+    }
+
+    #[test]
+    fn cadence_from_crank_revolutions() {
+        let mut crank = CrankCadence::default();
+        assert_eq!(crank.update((100, 1024)), None);
+        // Two revolutions in one second.
+        assert_eq!(crank.update((102, 2048)), Some(120.0));
+    }
+
+    #[test]
+    fn cadence_across_counter_wraps() {
+        let mut crank = CrankCadence::default();
+        crank.update((u16::MAX - 1, 65_000));
+        // Three revolutions and 1024 ticks later, both counters have wrapped.
+        assert_eq!(crank.update((1, 488)), Some(180.0));
+    }
+
+    #[test]
+    fn cadence_without_a_new_crank_event_is_none() {
+        let mut crank = CrankCadence::default();
+        crank.update((100, 1024));
+        assert_eq!(crank.update((100, 1024)), None);
+    }
+
+    #[test]
+    fn speed_across_counter_wraps() {
+        let start = Instant::now();
+        let mut wheel = WheelSpeed::new(2105);
+        assert_eq!(wheel.update(start, (u32::MAX - 1, 65_000)), None);
+        // Four revolutions of a 2105 mm wheel in one second, both counters have wrapped.
+        let kph = wheel
+            .update(start + Duration::from_secs(1), (2, 488))
+            .unwrap();
+        assert!((kph - 4.0 * 2.105 * 3.6).abs() < 1e-3, "{}", kph);
+    }
+
+    #[test]
+    fn speed_without_a_new_wheel_event_drops_to_zero() {
+        let start = Instant::now();
+        let mut wheel = WheelSpeed::new(2105);
+        wheel.update(start, (100, 1024));
+        assert!(wheel.update(start, (101, 2048)).is_some());
+
+        // The sensor repeats its last event while the wheel is still.
+        assert_eq!(
+            wheel.update(start + Duration::from_millis(500), (101, 2048)),
+            None
+        );
+        let stopped = start + WHEEL_STOPPED_AFTER;
+        assert_eq!(wheel.update(stopped, (101, 2048)), Some(0.0));
+    }
 }
@@ -0,0 +1,220 @@
+use std::{fmt, io, path::PathBuf};
+
+use reqwest::{multipart, Client, StatusCode};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+
+use super::UploadEvent;
+
+// Garmin has no public upload API, this follows the web login the Connect site itself uses.
+const SIGNIN_URL: &str = "https://sso.garmin.com/sso/signin";
+const SERVICE_URL: &str = "https://connect.garmin.com/modern";
+const UPLOAD_URL: &str = "https://connect.garmin.com/modern/proxy/upload-service/upload/.fit";
+const ACTIVITY_URL: &str = "https://connect.garmin.com/modern/activity";
+const KEYRING_SERVICE: &str = "bike-trainer-garmin-connect";
+// Some endpoints reject requests without a browser-like user agent.
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/118.0";
+
+#[derive(Debug)]
+pub(crate) enum GarminError {
+    Io(io::Error),
+    Http(reqwest::Error),
+    Keyring(keyring::Error),
+    Login(String),
+    Upload(String),
+}
+
+impl fmt::Display for GarminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GarminError::Io(e) => write!(f, "{}", e),
+            GarminError::Http(e) => write!(f, "request failed: {}", e),
+            GarminError::Keyring(keyring::Error::NoEntry) => {
+                write!(f, "no Garmin Connect password saved, add it in the settings")
+            }
+            GarminError::Keyring(e) => write!(f, "keychain error: {}", e),
+            GarminError::Login(reason) => write!(f, "login failed: {}", reason),
+            GarminError::Upload(reason) => write!(f, "upload failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for GarminError {}
+
+impl From<io::Error> for GarminError {
+    fn from(e: io::Error) -> Self {
+        GarminError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for GarminError {
+    fn from(e: reqwest::Error) -> Self {
+        GarminError::Http(e)
+    }
+}
+
+impl From<keyring::Error> for GarminError {
+    fn from(e: keyring::Error) -> Self {
+        GarminError::Keyring(e)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadResponse {
+    detailed_import_result: ImportResult,
+}
+
+#[derive(Deserialize)]
+struct ImportResult {
+    #[serde(default)]
+    successes: Vec<ImportSuccess>,
+    #[serde(default)]
+    failures: Vec<ImportFailure>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportSuccess {
+    internal_id: u64,
+}
+
+#[derive(Deserialize)]
+struct ImportFailure {
+    #[serde(default)]
+    messages: Vec<ImportMessage>,
+}
+
+#[derive(Deserialize)]
+struct ImportMessage {
+    content: String,
+}
+
+/// Saves the password in the OS keychain, it never goes in the settings file.
+pub(crate) fn save_password(username: &str, password: &str) -> Result<(), GarminError> {
+    keyring::Entry::new(KEYRING_SERVICE, username)?.set_password(password)?;
+    Ok(())
+}
+
+/// Logs in with the password from the keychain and uploads a FIT file.
+pub(crate) async fn upload_fit(username: String, path: PathBuf, events: Sender<UploadEvent>) {
+    let result: Result<String, GarminError> = async {
+        let _ = events.send(UploadEvent::Status("Logging in to Garmin Connect".into())).await;
+        let client = Client::builder()
+            .cookie_store(true)
+            .user_agent(USER_AGENT)
+            .build()?;
+        login(&client, &username).await?;
+
+        let _ = events.send(UploadEvent::Status("Uploading to Garmin Connect".into())).await;
+        upload(&client, path).await
+    }
+    .await;
+
+    let event = match result {
+        Ok(url) => UploadEvent::Done { url },
+        Err(e) => UploadEvent::Failed(e.to_string()),
+    };
+    let _ = events.send(event).await;
+}
+
+async fn login(client: &Client, username: &str) -> Result<(), GarminError> {
+    let user = username.to_string();
+    // Keychain access can block on an unlock prompt.
+    let password = tokio::task::spawn_blocking(move || {
+        keyring::Entry::new(KEYRING_SERVICE, &user)?.get_password()
+    })
+    .await
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))??;
+
+    let params = [
+        ("service", SERVICE_URL),
+        ("gauthHost", SIGNIN_URL),
+        ("embed", "true"),
+        ("clientId", "GarminConnect"),
+    ];
+    let page = client
+        .get(SIGNIN_URL)
+        .query(&params)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let csrf = find_between(&page, "name=\"_csrf\" value=\"", "\"")
+        .ok_or_else(|| GarminError::Login("sign-in page has no CSRF token".into()))?;
+
+    let response = client
+        .post(SIGNIN_URL)
+        .query(&params)
+        .header("Referer", SIGNIN_URL)
+        .form(&[
+            ("username", username),
+            ("password", password.as_str()),
+            ("embed", "true"),
+            ("_csrf", csrf),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let ticket = find_between(&response, "ticket=", "\"")
+        .ok_or_else(|| GarminError::Login("wrong username or password".into()))?;
+
+    // Trading the ticket in sets the session cookies the upload needs.
+    client
+        .get(SERVICE_URL)
+        .query(&[("ticket", ticket)])
+        .send()
+        .await?
+        .error_for_status()?;
+    info!("Logged in to Garmin Connect as {}", username);
+    Ok(())
+}
+
+async fn upload(client: &Client, path: PathBuf) -> Result<String, GarminError> {
+    let bytes = tokio::fs::read(&path).await?;
+    let file_name = path
+        .file_name()
+        .map_or("ride.fit".to_string(), |name| name.to_string_lossy().into_owned());
+    let form =
+        multipart::Form::new().part("file", multipart::Part::bytes(bytes).file_name(file_name));
+
+    let response = client
+        .post(UPLOAD_URL)
+        .header("NK", "NT")
+        .multipart(form)
+        .send()
+        .await?;
+    let status = response.status();
+    // 409 means the activity was uploaded before, the body still says which one.
+    if !status.is_success() && status != StatusCode::CONFLICT {
+        let message = response.text().await.unwrap_or_default();
+        return Err(GarminError::Upload(format!("{}: {}", status, message)));
+    }
+
+    let result = response.json::<UploadResponse>().await?.detailed_import_result;
+    if let Some(success) = result.successes.first() {
+        return Ok(format!("{}/{}", ACTIVITY_URL, success.internal_id));
+    }
+    let reason = result
+        .failures
+        .iter()
+        .flat_map(|failure| &failure.messages)
+        .map(|message| message.content.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if reason.is_empty() {
+        // Accepted but still processing, the activity shows up in Connect shortly.
+        return Ok(SERVICE_URL.to_string());
+    }
+    Err(GarminError::Upload(reason))
+}
+
+fn find_between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = text.find(start)? + start.len();
+    let len = text[from..].find(end)?;
+    Some(&text[from..from + len])
+}
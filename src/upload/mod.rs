@@ -0,0 +1,12 @@
+pub(crate) mod garmin;
+pub(crate) mod strava;
+
+/// Progress of an upload running on the runtime, drained by the UI.
+#[derive(Debug)]
+pub(crate) enum UploadEvent {
+    Status(String),
+    /// New Strava tokens are in the keychain, the access token expires at this unix time.
+    StravaTokens { expires_at: u64 },
+    Done { url: String },
+    Failed(String),
+}
@@ -0,0 +1,443 @@
+use std::{
+    fmt, io,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Query, State},
+    response::Html,
+    routing::get,
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::{multipart, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::{
+    sync::{mpsc::Sender, oneshot},
+    time,
+};
+use tracing::{info, warn};
+
+use super::UploadEvent;
+
+const AUTHORIZE_URL: &str = "https://www.strava.com/oauth/authorize";
+const TOKEN_URL: &str = "https://www.strava.com/oauth/token";
+const UPLOADS_URL: &str = "https://www.strava.com/api/v3/uploads";
+const ACTIVITY_URL: &str = "https://www.strava.com/activities";
+const SCOPE: &str = "activity:write";
+// Leaves the rider time to log in and approve the app in the browser.
+const AUTHORIZE_TIMEOUT: Duration = Duration::from_secs(300);
+// Refresh a minute early rather than race the expiry.
+const EXPIRY_MARGIN_SECS: u64 = 60;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 30;
+// Strava's short term limit resets at 0, 15, 30 and 45 minutes past the hour.
+const RATE_LIMIT_WINDOW_SECS: u64 = 15 * 60;
+const KEYRING_SERVICE: &str = "bike-trainer-strava";
+// Keychain entries under KEYRING_SERVICE.
+const CLIENT_SECRET: &str = "client-secret";
+const ACCESS_TOKEN: &str = "access-token";
+const REFRESH_TOKEN: &str = "refresh-token";
+
+#[derive(Clone, Debug)]
+pub(crate) struct Tokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Unix time in seconds.
+    pub expires_at: u64,
+}
+
+impl Tokens {
+    pub fn is_expired(&self) -> bool {
+        unix_now() + EXPIRY_MARGIN_SECS >= self.expires_at
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum StravaError {
+    Io(io::Error),
+    Http(reqwest::Error),
+    Keyring(keyring::Error),
+    Denied(String),
+    AuthorizeTimeout,
+    RateLimited,
+    Api { status: StatusCode, message: String },
+    Upload(String),
+}
+
+impl fmt::Display for StravaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StravaError::Io(e) => write!(f, "{}", e),
+            StravaError::Http(e) => write!(f, "request failed: {}", e),
+            StravaError::Keyring(keyring::Error::NoEntry) => {
+                write!(f, "no Strava client secret saved, add it in the settings")
+            }
+            StravaError::Keyring(e) => write!(f, "keychain error: {}", e),
+            StravaError::Denied(reason) => write!(f, "authorization denied: {}", reason),
+            StravaError::AuthorizeTimeout => write!(f, "authorization wasn't completed in time"),
+            StravaError::RateLimited => {
+                write!(f, "Strava's daily rate limit reached, try again tomorrow")
+            }
+            StravaError::Api { status, message } => {
+                write!(f, "Strava returned {}: {}", status, message)
+            }
+            StravaError::Upload(reason) => write!(f, "upload failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StravaError {}
+
+impl From<io::Error> for StravaError {
+    fn from(e: io::Error) -> Self {
+        StravaError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for StravaError {
+    fn from(e: reqwest::Error) -> Self {
+        StravaError::Http(e)
+    }
+}
+
+impl From<keyring::Error> for StravaError {
+    fn from(e: keyring::Error) -> Self {
+        StravaError::Keyring(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+    expires_at: u64,
+}
+
+impl From<TokenResponse> for Tokens {
+    fn from(response: TokenResponse) -> Self {
+        Self {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            expires_at: response.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Callback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UploadStatus {
+    id: u64,
+    status: String,
+    error: Option<String>,
+    activity_id: Option<u64>,
+}
+
+type CallbackSender = Arc<Mutex<Option<oneshot::Sender<Callback>>>>;
+
+/// Saves the client secret in the OS keychain, it never goes in the settings file.
+pub(crate) fn save_client_secret(secret: &str) -> Result<(), StravaError> {
+    keyring::Entry::new(KEYRING_SERVICE, CLIENT_SECRET)?.set_password(secret)?;
+    Ok(())
+}
+
+/// Uploads a FIT file with the secret and tokens from the keychain, authorizing or refreshing
+/// the tokens first when needed. `expires_at` is when the saved access token runs out, new
+/// tokens go back in the keychain and their expiry is sent to the app.
+pub(crate) async fn upload_fit(
+    client_id: String,
+    expires_at: u64,
+    path: PathBuf,
+    events: Sender<UploadEvent>,
+) {
+    let result: Result<u64, StravaError> = async {
+        let (client_secret, tokens) = keychain(move || load_credentials(expires_at)).await?;
+        let tokens = match tokens {
+            Some(tokens) if !tokens.is_expired() => tokens,
+            Some(tokens) => {
+                let _ = events.send(UploadEvent::Status("Refreshing Strava login".into())).await;
+                let tokens = refresh(&client_id, &client_secret, &tokens).await?;
+                remember(&tokens, &events).await;
+                tokens
+            }
+            None => {
+                let status = UploadEvent::Status("Waiting for Strava authorization".into());
+                let _ = events.send(status).await;
+                let tokens = authorize(&client_id, &client_secret).await?;
+                remember(&tokens, &events).await;
+                tokens
+            }
+        };
+
+        let _ = events.send(UploadEvent::Status("Uploading to Strava".into())).await;
+        upload(&tokens.access_token, &path).await
+    }
+    .await;
+
+    let event = match result {
+        Ok(activity_id) => UploadEvent::Done {
+            url: format!("{}/{}", ACTIVITY_URL, activity_id),
+        },
+        Err(e) => UploadEvent::Failed(e.to_string()),
+    };
+    let _ = events.send(event).await;
+}
+
+/// Runs the OAuth authorization code flow with PKCE, catching the redirect on a loopback port.
+pub(crate) async fn authorize(client_id: &str, client_secret: &str) -> Result<Tokens, StravaError> {
+    let verifier = random_string(64);
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    let state = random_string(16);
+
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    listener.set_nonblocking(true)?;
+    let redirect_uri = format!("http://127.0.0.1:{}/callback", listener.local_addr()?.port());
+
+    let (tx, rx) = oneshot::channel();
+    let router = Router::new()
+        .route("/callback", get(callback))
+        .with_state(Arc::new(Mutex::new(Some(tx))));
+    let server = axum::Server::from_tcp(listener)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    tokio::spawn(
+        server
+            .serve(router.into_make_service())
+            .with_graceful_shutdown(async {
+                let _ = shutdown_rx.await;
+            }),
+    );
+
+    let url = reqwest::Url::parse_with_params(
+        AUTHORIZE_URL,
+        &[
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("response_type", "code"),
+            ("approval_prompt", "auto"),
+            ("scope", SCOPE),
+            ("state", state.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .expect("valid authorize URL");
+    info!("Opening the browser to authorize Strava");
+    open::that(url.as_str())?;
+
+    let callback = time::timeout(AUTHORIZE_TIMEOUT, rx).await;
+    let _ = shutdown_tx.send(());
+    let callback = match callback {
+        Ok(Ok(callback)) => callback,
+        _ => return Err(StravaError::AuthorizeTimeout),
+    };
+    if let Some(error) = callback.error {
+        return Err(StravaError::Denied(error));
+    }
+    if callback.state.as_deref() != Some(state.as_str()) {
+        return Err(StravaError::Denied("state mismatch".into()));
+    }
+    let code = callback
+        .code
+        .ok_or_else(|| StravaError::Denied("no authorization code".into()))?;
+
+    let client = reqwest::Client::new();
+    let response = send(|| {
+        client.post(TOKEN_URL).form(&[
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("code", code.as_str()),
+            ("grant_type", "authorization_code"),
+            ("code_verifier", verifier.as_str()),
+        ])
+    })
+    .await?;
+    let tokens: TokenResponse = response.json().await?;
+    Ok(tokens.into())
+}
+
+pub(crate) async fn refresh(
+    client_id: &str,
+    client_secret: &str,
+    tokens: &Tokens,
+) -> Result<Tokens, StravaError> {
+    let client = reqwest::Client::new();
+    let response = send(|| {
+        client.post(TOKEN_URL).form(&[
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("refresh_token", tokens.refresh_token.as_str()),
+            ("grant_type", "refresh_token"),
+        ])
+    })
+    .await?;
+    let tokens: TokenResponse = response.json().await?;
+    Ok(tokens.into())
+}
+
+/// Uploads a FIT file and waits for Strava to turn it into an activity, returning its id.
+pub(crate) async fn upload(access_token: &str, path: &Path) -> Result<u64, StravaError> {
+    let bytes = tokio::fs::read(path).await?;
+    let file_name = path
+        .file_name()
+        .map_or("ride.fit".to_string(), |name| name.to_string_lossy().into_owned());
+
+    let client = reqwest::Client::new();
+    // A multipart body can't be cloned, so it's built again for a retry.
+    let response = send(|| {
+        let file = multipart::Part::bytes(bytes.clone()).file_name(file_name.clone());
+        let form = multipart::Form::new().text("data_type", "fit").part("file", file);
+        client.post(UPLOADS_URL).bearer_auth(access_token).multipart(form)
+    })
+    .await?;
+    let mut status: UploadStatus = response.json().await?;
+
+    // Uploads are processed in the background, poll until there's an activity or an error.
+    for _ in 0..POLL_ATTEMPTS {
+        if let Some(error) = status.error {
+            return Err(StravaError::Upload(error));
+        }
+        if let Some(activity_id) = status.activity_id {
+            return Ok(activity_id);
+        }
+
+        time::sleep(POLL_INTERVAL).await;
+        let url = format!("{}/{}", UPLOADS_URL, status.id);
+        status = send(|| client.get(&url).bearer_auth(access_token))
+            .await?
+            .json()
+            .await?;
+    }
+
+    Err(StravaError::Upload(format!("still {}", status.status.to_lowercase())))
+}
+
+/// Saves new tokens in the keychain and sends their expiry to the app. The upload goes on when
+/// that fails, the next one has to authorize again.
+async fn remember(tokens: &Tokens, events: &Sender<UploadEvent>) {
+    let saved = tokens.clone();
+    if let Err(e) = keychain(move || save_tokens(&saved)).await {
+        warn!("Failed to save the Strava tokens: {}", e);
+        return;
+    }
+    let expires_at = tokens.expires_at;
+    let _ = events.send(UploadEvent::StravaTokens { expires_at }).await;
+}
+
+/// The client secret, and the tokens of the last login if there was one.
+fn load_credentials(expires_at: u64) -> keyring::Result<(String, Option<Tokens>)> {
+    let client_secret = keyring::Entry::new(KEYRING_SERVICE, CLIENT_SECRET)?.get_password()?;
+    let tokens = load_token(ACCESS_TOKEN)?
+        .zip(load_token(REFRESH_TOKEN)?)
+        .map(|(access_token, refresh_token)| Tokens {
+            access_token,
+            refresh_token,
+            expires_at,
+        });
+    Ok((client_secret, tokens))
+}
+
+fn load_token(name: &str) -> keyring::Result<Option<String>> {
+    match keyring::Entry::new(KEYRING_SERVICE, name)?.get_password() {
+        Ok(token) => Ok(Some(token)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn save_tokens(tokens: &Tokens) -> keyring::Result<()> {
+    keyring::Entry::new(KEYRING_SERVICE, ACCESS_TOKEN)?.set_password(&tokens.access_token)?;
+    keyring::Entry::new(KEYRING_SERVICE, REFRESH_TOKEN)?.set_password(&tokens.refresh_token)
+}
+
+/// Runs keychain access on a blocking thread, it can wait on an unlock prompt.
+async fn keychain<T: Send + 'static>(
+    access: impl FnOnce() -> keyring::Result<T> + Send + 'static,
+) -> Result<T, StravaError> {
+    let result = tokio::task::spawn_blocking(access)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(result?)
+}
+
+async fn callback(
+    State(tx): State<CallbackSender>,
+    Query(params): Query<Callback>,
+) -> Html<&'static str> {
+    if let Some(tx) = tx.lock().unwrap().take() {
+        let _ = tx.send(params);
+    }
+    Html("<p>All set, you can close this tab and go back to the trainer.</p>")
+}
+
+/// Sends the request `build` makes. When it hits Strava's short term rate limit, waits for the
+/// next 15 minute window and sends it once more, a second refusal means the daily limit is used up.
+async fn send(build: impl Fn() -> RequestBuilder) -> Result<Response, StravaError> {
+    match check_status(build().send().await?).await {
+        Err(StravaError::RateLimited) => {
+            let wait = until_next_window(unix_now());
+            warn!("Strava rate limit reached, retrying in {} s", wait.as_secs());
+            time::sleep(wait).await;
+            check_status(build().send().await?).await
+        }
+        result => result,
+    }
+}
+
+/// Time left until the next rate limit window starts, at `now` in unix seconds.
+fn until_next_window(now: u64) -> Duration {
+    Duration::from_secs(RATE_LIMIT_WINDOW_SECS - now % RATE_LIMIT_WINDOW_SECS)
+}
+
+async fn check_status(response: Response) -> Result<Response, StravaError> {
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(StravaError::RateLimited);
+    }
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(StravaError::Api { status, message });
+    }
+    Ok(response)
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_the_next_quarter_hour() {
+        // 2023-10-01 12:00:00 UTC.
+        let noon = 1_696_161_600;
+        assert_eq!(until_next_window(noon), Duration::from_secs(15 * 60));
+        assert_eq!(until_next_window(noon + 1), Duration::from_secs(15 * 60 - 1));
+        assert_eq!(until_next_window(noon + 14 * 60 + 59), Duration::from_secs(1));
+        assert_eq!(until_next_window(noon + 15 * 60), Duration::from_secs(15 * 60));
+    }
+}
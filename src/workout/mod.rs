@@ -0,0 +1,119 @@
+use std::{fmt, fs, io, path::Path, time::Duration};
+
+use serde::Deserialize;
+
+pub(crate) mod zwo;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct WorkoutStep {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub duration_secs: u32,
+    pub target_power_pct_ftp: f32,
+}
+
+impl WorkoutStep {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration_secs as u64)
+    }
+
+    pub fn target_watts(&self, ftp: u16) -> u16 {
+        (ftp as f32 * self.target_power_pct_ftp / 100.0).round() as u16
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Workout {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub steps: Vec<WorkoutStep>,
+}
+
+#[derive(Debug)]
+pub(crate) enum WorkoutError {
+    Io(io::Error),
+    Yaml(serde_yaml::Error),
+    Xml(quick_xml::Error),
+    InvalidAttribute {
+        element: String,
+        attribute: &'static str,
+    },
+    Empty,
+}
+
+impl fmt::Display for WorkoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkoutError::Io(e) => write!(f, "failed to read workout: {}", e),
+            WorkoutError::Yaml(e) => write!(f, "invalid workout: {}", e),
+            WorkoutError::Xml(e) => write!(f, "invalid workout: {}", e),
+            WorkoutError::InvalidAttribute { element, attribute } => write!(
+                f,
+                "<{}> has a missing or invalid {} attribute",
+                element, attribute
+            ),
+            WorkoutError::Empty => write!(f, "workout has no steps"),
+        }
+    }
+}
+
+impl std::error::Error for WorkoutError {}
+
+impl From<io::Error> for WorkoutError {
+    fn from(e: io::Error) -> Self {
+        WorkoutError::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for WorkoutError {
+    fn from(e: serde_yaml::Error) -> Self {
+        WorkoutError::Yaml(e)
+    }
+}
+
+impl From<quick_xml::Error> for WorkoutError {
+    fn from(e: quick_xml::Error) -> Self {
+        WorkoutError::Xml(e)
+    }
+}
+
+impl Workout {
+    pub fn from_yaml(src: &str) -> Result<Self, WorkoutError> {
+        let workout: Workout = serde_yaml::from_str(src)?;
+        if workout.steps.is_empty() {
+            return Err(WorkoutError::Empty);
+        }
+        Ok(workout)
+    }
+
+    pub fn from_zwo(src: &str) -> Result<Self, WorkoutError> {
+        zwo::parse(src)
+    }
+
+    /// Loads a Zwift `.zwo` file or a YAML workout, picked by extension.
+    pub fn load(path: &Path) -> Result<Self, WorkoutError> {
+        let src = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("zwo") => Self::from_zwo(&src),
+            _ => Self::from_yaml(&src),
+        }
+    }
+
+    /// Each step alongside the elapsed time at which it begins.
+    pub fn schedule(&self) -> impl Iterator<Item = (Duration, &WorkoutStep)> {
+        self.steps.iter().scan(Duration::ZERO, |start, step| {
+            let step_start = *start;
+            *start += step.duration();
+            Some((step_start, step))
+        })
+    }
+
+    /// The step running at `elapsed` with its index and the time left in it, `None` once the
+    /// workout is over.
+    pub fn step_at(&self, elapsed: Duration) -> Option<(usize, &WorkoutStep, Duration)> {
+        self.schedule()
+            .enumerate()
+            .find(|(_, (start, step))| elapsed < *start + step.duration())
+            .map(|(i, (start, step))| (i, step, start + step.duration() - elapsed))
+    }
+}
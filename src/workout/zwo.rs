@@ -0,0 +1,100 @@
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use super::{Workout, WorkoutError, WorkoutStep};
+
+// Ramps are approximated by steady segments of this length.
+const RAMP_SEGMENT_SECS: u32 = 30;
+
+pub(crate) fn parse(src: &str) -> Result<Workout, WorkoutError> {
+    let mut reader = Reader::from_str(src);
+    reader.trim_text(true);
+
+    let mut name = None;
+    let mut in_name = false;
+    let mut steps = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"name" => in_name = true,
+            Event::End(e) if e.name().as_ref() == b"name" => in_name = false,
+            Event::Text(text) if in_name => name = Some(text.unescape()?.into_owned()),
+            Event::Start(e) | Event::Empty(e) => match e.name().as_ref() {
+                b"SteadyState" => steps.push(WorkoutStep {
+                    name: None,
+                    duration_secs: attribute(&e, "Duration")? as u32,
+                    target_power_pct_ftp: attribute(&e, "Power")? * 100.0,
+                }),
+                b"Warmup" => ramp(&e, "Warmup", &mut steps)?,
+                b"Cooldown" => ramp(&e, "Cooldown", &mut steps)?,
+                b"IntervalsT" => {
+                    let repeat = attribute(&e, "Repeat")? as u32;
+                    let on_secs = attribute(&e, "OnDuration")? as u32;
+                    let off_secs = attribute(&e, "OffDuration")? as u32;
+                    let on_power = attribute(&e, "OnPower")? * 100.0;
+                    let off_power = attribute(&e, "OffPower")? * 100.0;
+                    for _ in 0..repeat {
+                        steps.push(WorkoutStep {
+                            name: Some("On".to_string()),
+                            duration_secs: on_secs,
+                            target_power_pct_ftp: on_power,
+                        });
+                        steps.push(WorkoutStep {
+                            name: Some("Off".to_string()),
+                            duration_secs: off_secs,
+                            target_power_pct_ftp: off_power,
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if steps.is_empty() {
+        return Err(WorkoutError::Empty);
+    }
+    Ok(Workout { name, steps })
+}
+
+fn ramp(e: &BytesStart, name: &str, steps: &mut Vec<WorkoutStep>) -> Result<(), WorkoutError> {
+    let duration = attribute(e, "Duration")? as u32;
+    let low = attribute(e, "PowerLow")? * 100.0;
+    let high = attribute(e, "PowerHigh")? * 100.0;
+
+    let mut start = 0;
+    while start < duration {
+        let segment = RAMP_SEGMENT_SECS.min(duration - start);
+        let midpoint = (start as f32 + segment as f32 / 2.0) / duration as f32;
+        steps.push(WorkoutStep {
+            name: Some(name.to_string()),
+            duration_secs: segment,
+            target_power_pct_ftp: low + (high - low) * midpoint,
+        });
+        start += segment;
+    }
+    Ok(())
+}
+
+fn attribute(e: &BytesStart, name: &'static str) -> Result<f32, WorkoutError> {
+    let element = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+    let value = e
+        .try_get_attribute(name)?
+        .ok_or_else(|| WorkoutError::InvalidAttribute {
+            element: element.clone(),
+            attribute: name,
+        })?;
+
+    value
+        .unescape_value()?
+        .trim()
+        .parse()
+        .map_err(|_| WorkoutError::InvalidAttribute {
+            element,
+            attribute: name,
+        })
+}
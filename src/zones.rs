@@ -0,0 +1,76 @@
+use eframe::epaint::Color32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Zone {
+    ActiveRecovery,
+    Endurance,
+    Tempo,
+    Threshold,
+    Vo2Max,
+    Anaerobic,
+    Neuromuscular,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 7] = [
+        Zone::ActiveRecovery,
+        Zone::Endurance,
+        Zone::Tempo,
+        Zone::Threshold,
+        Zone::Vo2Max,
+        Zone::Anaerobic,
+        Zone::Neuromuscular,
+    ];
+
+    pub fn number(self) -> u8 {
+        self as u8 + 1
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Zone::ActiveRecovery => "Active Recovery",
+            Zone::Endurance => "Endurance",
+            Zone::Tempo => "Tempo",
+            Zone::Threshold => "Threshold",
+            Zone::Vo2Max => "VO2 Max",
+            Zone::Anaerobic => "Anaerobic",
+            Zone::Neuromuscular => "Neuromuscular",
+        }
+    }
+
+    pub fn color(self) -> Color32 {
+        match self {
+            Zone::ActiveRecovery => Color32::GRAY,
+            Zone::Endurance => Color32::BLUE,
+            Zone::Tempo => Color32::GREEN,
+            Zone::Threshold => Color32::YELLOW,
+            Zone::Vo2Max => Color32::from_rgb(255, 140, 0),
+            Zone::Anaerobic => Color32::RED,
+            Zone::Neuromuscular => Color32::from_rgb(128, 0, 128),
+        }
+    }
+}
+
+pub(crate) struct PowerZones {
+    ftp: u16,
+}
+
+impl PowerZones {
+    pub fn new(ftp: u16) -> Self {
+        Self { ftp }
+    }
+
+    /// Classifies `watts` using Coggan's seven zones as a percentage of FTP.
+    pub fn zone_for(&self, watts: u16) -> Zone {
+        let percent = watts as f32 * 100.0 / self.ftp.max(1) as f32;
+        match percent {
+            p if p <= 55.0 => Zone::ActiveRecovery,
+            p if p <= 75.0 => Zone::Endurance,
+            p if p <= 90.0 => Zone::Tempo,
+            p if p <= 105.0 => Zone::Threshold,
+            p if p <= 120.0 => Zone::Vo2Max,
+            p if p <= 150.0 => Zone::Anaerobic,
+            _ => Zone::Neuromuscular,
+        }
+    }
+}